@@ -1,4 +1,5 @@
 use crate::{SERVICE_PIPE_NAME_WIDE, Cli, pipe};
+use std::io::Write;
 
 pub fn run(cli: &Cli) -> String {
 
@@ -20,3 +21,21 @@ pub fn run(cli: &Cli) -> String {
         }
     }
 }
+
+/// Sends a command whose reply is streamed and prints every message as it
+/// arrives, until the manager closes the connection.
+pub fn stream(cli: &Cli) -> Result<(), String> {
+    let msg = serde_json::to_string(&cli).map_err(|e| format!("Failed to serialize CLI message: {}", e))?;
+
+    let mut connection = pipe::connect(SERVICE_PIPE_NAME_WIDE)
+        .map_err(|e| format!("Failed to connect to pipe: {}", e))?;
+    connection.write(msg.as_bytes())
+        .map_err(|e| format!("Failed to send message to pipe: {}", e))?;
+
+    let mut stdout = std::io::stdout();
+    while let Some(message) = connection.read().map_err(|e| format!("Failed to read from pipe: {}", e))? {
+        stdout.write_all(&message).and_then(|()| stdout.flush())
+            .map_err(|e| format!("Failed to print: {}", e))?;
+    }
+    Ok(())
+}
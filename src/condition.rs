@@ -0,0 +1,82 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::fs;
+use chrono::{DateTime, Local};
+use crate::config::Unit;
+
+type Probe = fn(&str) -> bool;
+
+type Record = (DateTime<Local>, Outcome);
+
+static LAST_RESULT: Lazy<Mutex<HashMap<String, Record>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// A `condition_*` did not hold, the start is skipped.
+    ConditionFailed(String),
+    /// An `assert_*` did not hold, the start fails.
+    AssertionFailed(String),
+}
+
+/// Evaluates the conditions and assertions of `unit` and remembers the result
+/// for `status`. Assertions are checked first, as systemd does.
+pub fn evaluate(unit: &Unit) -> Outcome {
+    let outcome = match first_failure(
+        &unit.assert_path_exists,
+        &unit.assert_file_not_empty,
+        &unit.assert_env,
+        "assert",
+    ) {
+        Some(failed) => Outcome::AssertionFailed(failed),
+        None => match first_failure(
+            &unit.condition_path_exists,
+            &unit.condition_file_not_empty,
+            &unit.condition_env,
+            "condition",
+        ) {
+            Some(failed) => Outcome::ConditionFailed(failed),
+            None => Outcome::Passed,
+        },
+    };
+
+    LAST_RESULT.lock().unwrap().insert(unit.name.clone(), (Local::now(), outcome.clone()));
+    outcome
+}
+
+pub fn last(name: &str) -> Option<Record> {
+    LAST_RESULT.lock().unwrap().get(name).cloned()
+}
+
+fn first_failure(
+    path_exists: &Option<Vec<String>>,
+    file_not_empty: &Option<Vec<String>>,
+    env: &Option<Vec<String>>,
+    prefix: &str,
+) -> Option<String> {
+    let checks: [(&str, &Option<Vec<String>>, Probe); 3] = [
+        ("path_exists", path_exists, |path| fs::metadata(path).is_ok()),
+        ("file_not_empty", file_not_empty, |path| {
+            fs::metadata(path).map(|meta| meta.is_file() && meta.len() > 0).unwrap_or(false)
+        }),
+        ("env", env, |expected| match expected.split_once('=') {
+            Some((key, value)) => std::env::var(key).is_ok_and(|actual| actual == value),
+            None => std::env::var_os(expected).is_some(),
+        }),
+    ];
+
+    for (kind, values, check) in checks {
+        for value in values.iter().flatten() {
+            // A leading `!` negates the check.
+            let holds = match value.strip_prefix('!') {
+                Some(value) => !check(value),
+                None => check(value),
+            };
+            if !holds {
+                return Some(format!("{}_{} = {:?}", prefix, kind, value));
+            }
+        }
+    }
+    None
+}
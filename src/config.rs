@@ -1,44 +1,282 @@
 use std::collections::HashMap;
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 use toml::Value;
 use once_cell::sync::Lazy;
 use std::sync::RwLock;
 use std::fs;
+use std::path::{Path, PathBuf};
 use core::fmt;
+use std::time::Duration;
+use log::LevelFilter;
+use crate::unit_file;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct Unit {
     pub name: String,
     pub description: Option<String>,
+    /// Units that must be started before this one.
+    pub after: Option<Vec<String>>,
+    /// Units that are started along with this one.
+    pub requires: Option<Vec<String>>,
+
+    /// Start is skipped unless every listed path exists. A leading `!` negates.
+    pub condition_path_exists: Option<Vec<String>>,
+    /// Start is skipped unless every listed file exists and is not empty.
+    pub condition_file_not_empty: Option<Vec<String>>,
+    /// Start is skipped unless every `NAME` is set, or `NAME=value` matches.
+    pub condition_env: Option<Vec<String>>,
+    /// Like `condition_path_exists`, but a failure makes the start fail.
+    pub assert_path_exists: Option<Vec<String>>,
+    /// Like `condition_file_not_empty`, but a failure makes the start fail.
+    pub assert_file_not_empty: Option<Vec<String>>,
+    /// Like `condition_env`, but a failure makes the start fail.
+    pub assert_env: Option<Vec<String>>,
 
     #[serde(flatten)]
     pub other: HashMap<String, Value>,
 }
 
-#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum ServiceType {
     Simple,
     Startup
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum RestartPolicy {
+    No,
+    Always,
+    OnSuccess,
+    OnFailure,
+}
+
+impl RestartPolicy {
+    /// Whether a process that exited on its own, successfully or not,
+    /// is started again.
+    pub fn should_restart(&self, success: bool) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::OnSuccess => success,
+            RestartPolicy::OnFailure => !success,
+        }
+    }
+}
+
+/// Which processes are terminated when a service is stopped.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum KillMode {
+    /// Every process the service started.
+    #[default]
+    ControlGroup,
+    /// Only the main process, helpers are left running.
+    Process,
+    /// The main process is asked to close first, the rest are killed after it.
+    Mixed,
+}
+
+/// What happens to a log file left by the previous run.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub enum OutputMode {
+    #[default]
+    Truncate,
+    Append,
+}
+
+/// Where an output stream of a service goes, written as a string such as
+/// `file:D:\logs\app.log` or `null`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub enum Output {
+    /// `file:<path>`, truncated on every start.
+    File(String),
+    /// `append:<path>`, kept across runs.
+    Append(String),
+    /// `journal`, only recorded in the journal.
+    Journal,
+    /// `null`, discarded.
+    Null,
+    /// `inherit`, the output of the manager itself.
+    Inherit,
+    /// `stdout`, for stderr only: wherever stdout goes.
+    Stdout,
+}
+
+impl TryFrom<String> for Output {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("file:") {
+            return Ok(Output::File(path.to_string()));
+        }
+        if let Some(path) = value.strip_prefix("append:") {
+            return Ok(Output::Append(path.to_string()));
+        }
+        match value.as_str() {
+            "journal" => Ok(Output::Journal),
+            "null" => Ok(Output::Null),
+            "inherit" => Ok(Output::Inherit),
+            "stdout" => Ok(Output::Stdout),
+            _ => Err(format!("invalid output `{}`, expected file:<path>, append:<path>, journal, null, inherit or stdout", value)),
+        }
+    }
+}
+
+impl From<Output> for String {
+    fn from(output: Output) -> Self {
+        output.to_string()
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::File(path) => write!(f, "file:{}", path),
+            Output::Append(path) => write!(f, "append:{}", path),
+            Output::Journal => write!(f, "journal"),
+            Output::Null => write!(f, "null"),
+            Output::Inherit => write!(f, "inherit"),
+            Output::Stdout => write!(f, "stdout"),
+        }
+    }
+}
+
+/// Where the stdin of a service comes from: `null`, `file:<path>` or `data`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub enum Input {
+    /// Reads end immediately.
+    Null,
+    File(String),
+    /// The text in `stdin_data`.
+    Data,
+}
+
+impl TryFrom<String> for Input {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("file:") {
+            return Ok(Input::File(path.to_string()));
+        }
+        match value.as_str() {
+            "null" => Ok(Input::Null),
+            "data" => Ok(Input::Data),
+            _ => Err(format!("invalid input `{}`, expected null, file:<path> or data", value)),
+        }
+    }
+}
+
+impl From<Input> for String {
+    fn from(input: Input) -> Self {
+        input.to_string()
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Null => write!(f, "null"),
+            Input::File(path) => write!(f, "file:{}", path),
+            Input::Data => write!(f, "data"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Service {
     #[serde(rename = "type")]
     pub style: ServiceType,
     pub path: String,
     pub args: Option<Vec<String>>,
     pub env: Option<HashMap<String, String>>,
-    pub stdout_path: Option<String>,
-    pub stderr_path: Option<String>,
+    /// Defaults to `data` if `stdin_data` is set, `null` otherwise.
+    pub stdin: Option<Input>,
+    /// Written to stdin with `stdin = "data"`, then stdin is closed.
+    pub stdin_data: Option<String>,
+    /// Defaults to `file:` the log directory.
+    pub stdout: Option<Output>,
+    /// Defaults to the same kind of target as `stdout`, with its own file
+    /// in the log directory for `file:` and `append:`.
+    pub stderr: Option<Output>,
+    /// Prefix every line written to the log files with a timestamp and
+    /// `[stdout]` or `[stderr]`.
+    pub log_timestamps: Option<bool>,
+    /// Rotate a log file once it would grow past this many bytes.
+    pub log_max_size: Option<u64>,
+    /// Rotate a log file once it has been written to for this many seconds.
+    pub log_max_age_sec: Option<u64>,
+    /// Rotated files kept per stream, 5 by default.
+    pub log_max_files: Option<usize>,
+    /// Compress rotated files with gzip.
+    pub log_compress: Option<bool>,
+    pub working_directory: Option<String>,
+    pub restart: Option<RestartPolicy>,
+    /// Seconds to wait before a restart, 1 by default. Doubled for every
+    /// further restart within `start_limit_interval_sec`.
+    pub restart_sec: Option<u64>,
+    /// Upper bound of the doubled restart delay in seconds, 60 by default.
+    pub restart_max_delay_sec: Option<u64>,
+    /// Window in seconds for `start_limit_burst`, 60 by default.
+    pub start_limit_interval_sec: Option<u64>,
+    /// Automatic restarts allowed within `start_limit_interval_sec`, 5 by
+    /// default. One more exit in that window makes the unit `failed`.
+    pub start_limit_burst: Option<usize>,
+    /// Seconds to wait for the process to exit after asking it to close
+    /// before it is killed.
+    pub timeout_stop_sec: Option<u64>,
+    /// Seconds the process must stay alive before `start` reports it as
+    /// started. `start` returns right after spawning if unset.
+    pub timeout_start_sec: Option<u64>,
+    pub kill_mode: Option<KillMode>,
+    /// The process reports `READY=1` through the pipe in `NOTIFY_SOCKET`,
+    /// and `start` waits for it instead of a fixed time.
+    pub notify: Option<bool>,
+    /// Seconds within which the process must send `WATCHDOG=1` through the
+    /// notify pipe, or it is treated as hung, stopped and restarted.
+    pub watchdog_sec: Option<u64>,
+    /// Exit codes besides 0 that count as a successful exit.
+    pub success_exit_status: Option<Vec<u32>>,
+    /// Exit codes after which the unit is never restarted, whatever `restart` says.
+    pub restart_prevent_exit_status: Option<Vec<u32>>,
 
     #[serde(flatten)]
     pub other: HashMap<String, Value>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// How the health of a running service is probed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "probe")]
+pub enum Probe {
+    /// A TCP connection to `address`, e.g. `127.0.0.1:8080`, must succeed.
+    Tcp { address: String },
+    /// A GET of an `http://` URL must answer with `expected_status`, 200 by default.
+    Http { url: String, expected_status: Option<u16> },
+    /// A command that must exit with code 0.
+    Command { path: String, args: Option<Vec<String>> },
+}
+
+/// Periodic health check of a running service, the `[health]` section.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Health {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Seconds between probes, 10 by default.
+    pub interval_sec: Option<u64>,
+    /// Seconds a probe may take before it counts as failed, 5 by default.
+    pub timeout_sec: Option<u64>,
+    /// Consecutive failures after which the unit is unhealthy, 3 by default.
+    pub failure_threshold: Option<u32>,
+    /// Restart the unit once it is unhealthy.
+    pub restart: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Config {
     pub unit: Unit,
     pub service: Service,
+    pub health: Option<Health>,
     
     #[serde(flatten)]
     pub other: HashMap<String, Value>,
@@ -47,12 +285,83 @@ pub struct Config {
 
 
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ShutdownPolicy {
+    /// Stop every running service in reverse dependency order.
+    StopAll,
+    /// Leave services running, they are adopted again on the next start.
+    LeaveRunning,
+}
+
+/// How the manager writes `Systemd.log`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub enum LogFormat {
+    /// `[time][level][file:line] message` lines.
+    #[default]
+    Text,
+    /// One JSON object per line with `ts`, `level`, `module`, `unit`,
+    /// `event`, `pid`, `exit_code` and `message`.
+    Json,
+}
+
+/// Settings of the manager itself, read from `Systemd.toml` next to the executable.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub shutdown: ShutdownPolicy,
+    /// Stop timeout for units that do not set `timeout_stop_sec`.
+    pub default_timeout_stop_sec: u64,
+    /// How long `notify` units that do not set `timeout_start_sec` are given
+    /// to report `READY=1`.
+    pub default_timeout_start_sec: u64,
+    /// Record the output of every unit together with manager events in
+    /// `log/journal.jsonl`. Log files a unit writes itself are read back.
+    pub journal: bool,
+    /// Bytes after which the journal is rotated.
+    pub journal_max_size: u64,
+    /// Rotated journal files kept.
+    pub journal_max_files: usize,
+    pub log_format: LogFormat,
+    /// Level of the manager log, `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    pub log_level: LevelFilter,
+    /// Levels of single modules, e.g. `health = "debug"`.
+    pub log_modules: HashMap<String, LevelFilter>,
+    /// Bytes after which the manager log is rotated.
+    pub log_max_size: u64,
+    /// Rotated manager log files kept.
+    pub log_max_files: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            shutdown: ShutdownPolicy::StopAll,
+            default_timeout_stop_sec: 10,
+            default_timeout_start_sec: 90,
+            journal: true,
+            journal_max_size: 64 * 1024 * 1024,
+            journal_max_files: 5,
+            log_format: LogFormat::Text,
+            log_level: LevelFilter::Info,
+            log_modules: HashMap::new(),
+            log_max_size: 10 * 1024 * 1024,
+            log_max_files: 5,
+        }
+    }
+}
+
 static CONFIG_MAP: Lazy<RwLock<HashMap<String, Config>>> = Lazy::new(|| { RwLock::new(HashMap::new()) });
 
+static SETTINGS: Lazy<RwLock<Settings>> = Lazy::new(|| { RwLock::new(Settings::default()) });
+
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Toml(toml::de::Error),
+    TomlSer(toml::ser::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Parse(String),
 }
 
 impl fmt::Display for Error {
@@ -60,6 +369,10 @@ impl fmt::Display for Error {
         match self {
             Error::Io(e) => write!(f, "Io({})", e),
             Error::Toml(e) => write!(f, "Toml({})", e),
+            Error::TomlSer(e) => write!(f, "TomlSer({})", e),
+            Error::Json(e) => write!(f, "Json({})", e),
+            Error::Yaml(e) => write!(f, "Yaml({})", e),
+            Error::Parse(e) => write!(f, "Parse({})", e),
         }
     }
     
@@ -77,27 +390,142 @@ impl From<toml::de::Error> for Error {
     }
 }
 
-pub fn load() -> Result<(), Error> {
-    let mut config_map = CONFIG_MAP.write().unwrap();
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Error::TomlSer(err)
+    }
+}
 
-    config_map.clear();
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Error::Yaml(err)
+    }
+}
 
-    let config_path = 
-        std::env::current_exe()?
-            .parent()
-            .ok_or_else(|| {
-                std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find parent directory")
-            })?
-            .join("configs");
+#[derive(Debug, Default)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Parses a unit file according to its extension, returning `None` for files
+/// that are not unit definitions.
+fn parse_file(path: &Path) -> Result<Option<Config>, Error> {
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let mut config = match extension.as_deref() {
+        Some("toml") => toml::from_str(&fs::read_to_string(path)?)?,
+        Some("json") => serde_json::from_str(&fs::read_to_string(path)?)?,
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&fs::read_to_string(path)?)?,
+        Some("service") => {
+            let (config, untranslated) = unit_file::parse(&fs::read_to_string(path)?, &file_stem(path))?;
+            for directive in untranslated {
+                log::warn!("{:?}: cannot translate {}", path, directive);
+            }
+            config
+        }
+        _ => return Ok(None),
+    };
+
+    upgrade_outputs(&mut config, path)?;
+    Ok(Some(config))
+}
+
+fn legacy_string(other: &mut HashMap<String, Value>, key: &str) -> Result<Option<String>, Error> {
+    match other.remove(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(value) => Err(Error::Parse(format!("`{}` must be a string, found {}", key, value))),
+    }
+}
+
+/// Maps the `stdout_path`, `stderr_path`, `stdout_mode` and `stderr_mode`
+/// keys of older unit files onto `stdout` and `stderr`. A stream without
+/// either key keeps following `stdout` as before.
+fn upgrade_outputs(config: &mut Config, path: &Path) -> Result<(), Error> {
+    let service = &mut config.service;
+    let stdout_mode = legacy_string(&mut service.other, "stdout_mode")?;
+    let stderr_mode = legacy_string(&mut service.other, "stderr_mode")?;
+    let stdout_path = legacy_string(&mut service.other, "stdout_path")?;
+    let stderr_path = legacy_string(&mut service.other, "stderr_path")?;
+
+    let streams = [
+        ("stdout", stdout_path, stdout_mode, &mut service.stdout),
+        ("stderr", stderr_path, stderr_mode, &mut service.stderr),
+    ];
+    for (stream, file, mode, target) in streams {
+        if file.is_none() && mode.is_none() {
+            continue;
+        }
+        if let Some(output) = target {
+            log::warn!("{:?}: ignoring {}_path and {}_mode as `{} = \"{}\"` is set", path, stream, stream, stream, output);
+            continue;
+        }
+        let file = match file {
+            Some(file) => file,
+            None => crate::process::log_file(&config.unit.name, stream)?,
+        };
+        let output = match mode.as_deref() {
+            None | Some("truncate") => Output::File(file),
+            Some("append") => Output::Append(file),
+            Some(mode) => {
+                return Err(Error::Parse(format!("invalid {}_mode `{}`, expected truncate or append", stream, mode)));
+            }
+        };
+        log::warn!("{:?}: {}_path and {}_mode are deprecated, use `{} = \"{}\"`", path, stream, stream, stream, output);
+        *target = Some(output);
+    }
+    Ok(())
+}
+
+pub fn config_dir() -> std::io::Result<PathBuf> {
+    Ok(std::env::current_exe()?
+        .parent()
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find parent directory")
+        })?
+        .join("configs"))
+}
+
+pub fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_all() -> Result<HashMap<String, Config>, Error> {
+    let mut config_map = HashMap::new();
+
+    let config_path = config_dir()?;
     log::info!("Loading configuration from: {:?}", config_path);
     for entry in fs::read_dir(config_path)? {
         match entry {
             Ok(entry) => {
                 let path = entry.path();
                 if path.is_file() {
-                    let content = fs::read_to_string(&path)?;
-                    let config: Config = toml::from_str(&content)?;
-                    config_map.insert(config.unit.name.clone(), config);
+                    match parse_file(&path)? {
+                        Some(config) => {
+                            config_map.insert(config.unit.name.clone(), config);
+                        }
+                        None => {
+                            log::debug!("Skipping non-unit file: {:?}", path);
+                        }
+                    }
                 }
             }
             Err(err) => {
@@ -106,9 +534,118 @@ pub fn load() -> Result<(), Error> {
         }
     }
 
+    Ok(config_map)
+}
+
+fn read_settings() -> Result<Settings, Error> {
+    let path = std::env::current_exe()?.with_file_name(format!("{}.toml", crate::APP_NAME));
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// Rejects output targets that cannot work: `stdout` on stdout itself, and
+/// `journal` while the journal is turned off, which would discard the output.
+fn validate(config_map: &HashMap<String, Config>, settings: &Settings) -> Result<(), Error> {
+    for (name, config) in config_map {
+        if config.service.stdout == Some(Output::Stdout) {
+            return Err(Error::Parse(format!("{}: stdout cannot be sent to stdout", name)));
+        }
+        let journaled = [&config.service.stdout, &config.service.stderr]
+            .into_iter()
+            .any(|output| output == &Some(Output::Journal));
+        if journaled && !settings.journal {
+            return Err(Error::Parse(format!("{}: output is sent to the journal, but `journal` is turned off", name)));
+        }
+    }
     Ok(())
 }
 
+pub fn settings() -> Settings {
+    SETTINGS.read().unwrap().clone()
+}
+
+pub fn log_format() -> LogFormat {
+    SETTINGS.read().unwrap().log_format
+}
+
+/// Whether the journal is enabled, without cloning the settings.
+pub fn journal_enabled() -> bool {
+    SETTINGS.read().unwrap().journal
+}
+
+/// Whether `code` is an exit code after which `name` must not be restarted.
+pub fn prevents_restart(name: &str, code: u32) -> bool {
+    get(name, |config| {
+        config.service.restart_prevent_exit_status.iter().flatten().any(|&c| c == code)
+    }).unwrap_or(false)
+}
+
+/// Returns how long `name` is given to exit on its own when stopped.
+pub fn stop_timeout(name: &str) -> Duration {
+    let default = SETTINGS.read().unwrap().default_timeout_stop_sec;
+    let secs = get(name, |config| config.service.timeout_stop_sec).flatten().unwrap_or(default);
+    Duration::from_secs(secs)
+}
+
+/// Whether a service gets a notify pipe, because it reports readiness or
+/// sends watchdog keep-alives.
+pub fn uses_notify(service: &Service) -> bool {
+    service.notify == Some(true) || service.watchdog_sec.is_some()
+}
+
+/// Returns how long `start` waits for `name` to become ready, `None` if it
+/// is considered started as soon as it is spawned.
+pub fn start_timeout(name: &str) -> Option<Duration> {
+    let default = SETTINGS.read().unwrap().default_timeout_start_sec;
+    get(name, |config| match (config.service.timeout_start_sec, config.service.notify) {
+        (Some(secs), _) => Some(Duration::from_secs(secs)),
+        (None, Some(true)) => Some(Duration::from_secs(default)),
+        (None, _) => None,
+    }).flatten()
+}
+
+pub fn load() -> Result<(), Error> {
+    let new_settings = read_settings()?;
+    let new_map = read_all()?;
+    validate(&new_map, &new_settings)?;
+    *SETTINGS.write().unwrap() = new_settings;
+    *CONFIG_MAP.write().unwrap() = new_map;
+    Ok(())
+}
+
+/// Reloads every unit file and reports how the new set differs from the old one.
+/// The previous configuration is kept if any file fails to load.
+pub fn reload() -> Result<Diff, Error> {
+    let new_settings = read_settings()?;
+    let new_map = read_all()?;
+    validate(&new_map, &new_settings)?;
+    *SETTINGS.write().unwrap() = new_settings;
+    let mut config_map = CONFIG_MAP.write().unwrap();
+
+    let mut diff = Diff::default();
+    for (name, config) in new_map.iter() {
+        match config_map.get(name) {
+            Some(old) if old != config => diff.changed.push(name.clone()),
+            Some(_) => {}
+            None => diff.added.push(name.clone()),
+        }
+    }
+    for name in config_map.keys() {
+        if !new_map.contains_key(name) {
+            diff.removed.push(name.clone());
+        }
+    }
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+
+    *config_map = new_map;
+    Ok(diff)
+}
+
 
 
 pub fn get<F, R>(name: &str, f: F) -> Option<R>
@@ -121,12 +658,32 @@ where
 
 
 
-pub fn for_each<F>(mut f: F)
-where
-    F: FnMut(&Config),
-{
+/// Returns every unit name ordered so that each unit comes after the units
+/// listed in its `after` and `requires`. Cycles are broken arbitrarily.
+pub fn start_order() -> Vec<String> {
     let config_map = CONFIG_MAP.read().unwrap();
-    for config in config_map.values() {
-        f(config);
+
+    fn visit(name: &str, config_map: &HashMap<String, Config>, visited: &mut Vec<String>, order: &mut Vec<String>) {
+        if visited.iter().any(|v| v == name) {
+            return;
+        }
+        visited.push(name.to_string());
+        if let Some(config) = config_map.get(name) {
+            let deps = config.unit.after.iter().chain(config.unit.requires.iter()).flatten();
+            for dep in deps {
+                visit(dep, config_map, visited, order);
+            }
+            order.push(name.to_string());
+        }
+    }
+
+    let mut names: Vec<&String> = config_map.keys().collect();
+    names.sort();
+
+    let mut visited = Vec::new();
+    let mut order = Vec::new();
+    for name in names {
+        visit(name, &config_map, &mut visited, &mut order);
     }
+    order
 }
@@ -0,0 +1,72 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use crate::config::{Health, Probe};
+use crate::probe;
+
+const DEFAULT_INTERVAL_SEC: u64 = 10;
+const DEFAULT_TIMEOUT_SEC: u64 = 5;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+static HEALTH: Lazy<Mutex<HashMap<String, Status>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Result of the latest probes of a unit.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub healthy: bool,
+    /// Probes that failed in a row.
+    pub failures: u32,
+    pub checked: DateTime<Local>,
+    /// Why the last probe failed.
+    pub error: Option<String>,
+}
+
+pub fn interval(health: &Health) -> Duration {
+    Duration::from_secs(health.interval_sec.unwrap_or(DEFAULT_INTERVAL_SEC))
+}
+
+/// Runs the probe of `name` and records the result. Returns `true` when this
+/// probe made the unit unhealthy.
+pub fn check(name: &str, health: &Health) -> bool {
+    let timeout = Duration::from_secs(health.timeout_sec.unwrap_or(DEFAULT_TIMEOUT_SEC));
+    let threshold = health.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD).max(1);
+    let result = probe(&health.probe, timeout);
+
+    let mut states = HEALTH.lock().unwrap();
+    let failures = match states.get(name) {
+        Some(status) if result.is_err() => status.failures + 1,
+        None if result.is_err() => 1,
+        _ => 0,
+    };
+    let was_healthy = states.get(name).is_none_or(|status| status.healthy);
+    let healthy = failures < threshold;
+    if let Err(e) = &result {
+        log::warn!(unit = name, event = "health_check_failed"; "Health check of service {} failed ({}/{}): {}", name, failures, threshold, e);
+    }
+    states.insert(name.to_string(), Status {
+        healthy,
+        failures,
+        checked: Local::now(),
+        error: result.err(),
+    });
+    was_healthy && !healthy
+}
+
+/// Forgets the probes of the previous run of `name`.
+pub fn reset(name: &str) {
+    HEALTH.lock().unwrap().remove(name);
+}
+
+pub fn get(name: &str) -> Option<Status> {
+    HEALTH.lock().unwrap().get(name).cloned()
+}
+
+fn probe(probe: &Probe, timeout: Duration) -> Result<(), String> {
+    match probe {
+        Probe::Tcp { address } => probe::tcp(address, timeout),
+        Probe::Http { url, expected_status } => probe::http(url, expected_status.unwrap_or(200), timeout),
+        Probe::Command { path, args } => probe::command(path, args.as_deref().unwrap_or_default(), timeout),
+    }
+}
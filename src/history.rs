@@ -0,0 +1,117 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use chrono::{DateTime, Local};
+use crate::journal;
+
+/// Number of runs kept per unit.
+const MAX_RUNS: usize = 20;
+
+static HISTORY: Lazy<Mutex<HashMap<String, VecDeque<Run>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Who started or stopped a run.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Cause {
+    /// A `start` or `stop` command.
+    User,
+    /// Started with the manager because the unit is of type `Startup`.
+    Startup,
+    /// Started because another unit requires it.
+    Requirement,
+    /// Restarted after an exit according to `restart`.
+    RestartPolicy,
+    /// Started or stopped by `reload-config --apply`.
+    Reload,
+    /// Stopped because the manager shut down.
+    Shutdown,
+    /// Already running when the manager started and adopted from the state file.
+    Adopted,
+    /// Stopped because it did not send `WATCHDOG=1` in time.
+    Watchdog,
+    /// Stopped and started again because its health check failed.
+    HealthCheck,
+    /// Stopped because it did not report `READY=1` within its start timeout.
+    StartTimeout,
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub pid: u32,
+    pub start: DateTime<Local>,
+    pub started_by: Cause,
+    pub stop: Option<DateTime<Local>>,
+    pub exit_code: Option<u32>,
+    /// Whether the exit code is 0 or listed in `success_exit_status`.
+    pub success: bool,
+    /// `None` if the process exited on its own.
+    pub stopped_by: Option<Cause>,
+}
+
+impl Run {
+    /// Whether the process ended on its own with an exit code that does not
+    /// count as success, or was stopped by the watchdog, a health check or
+    /// its start timeout.
+    pub fn is_failure(&self) -> bool {
+        match self.stopped_by {
+            None => self.exit_code.is_some() && !self.success,
+            Some(cause) => matches!(cause, Cause::Watchdog | Cause::HealthCheck | Cause::StartTimeout),
+        }
+    }
+
+    /// One line summary of how the run ended.
+    pub fn outcome(&self) -> String {
+        match (self.exit_code, self.stopped_by) {
+            (None, _) => "running".to_string(),
+            (Some(0), None) => "exited with code 0".to_string(),
+            (Some(code), None) if self.success => format!("exited with code {} (success)", code),
+            (Some(code), None) => format!("exited with code {}", code),
+            (Some(code), Some(cause)) => format!("stopped by {:?} (code {})", cause, code),
+        }
+    }
+}
+
+pub fn started(name: &str, pid: u32, start: DateTime<Local>, started_by: Cause) {
+    journal::write(name, journal::MANAGER, Some(pid), &format!("started by {:?}", started_by));
+    let mut history = HISTORY.lock().unwrap();
+    let runs = history.entry(name.to_string()).or_default();
+    if runs.len() == MAX_RUNS {
+        runs.pop_front();
+    }
+    runs.push_back(Run {
+        pid,
+        start,
+        started_by,
+        stop: None,
+        exit_code: None,
+        success: false,
+        stopped_by: None,
+    });
+}
+
+/// Records the end of the latest run of `name`, unless it was already recorded.
+pub fn ended(name: &str, exit_code: u32, success: bool, stopped_by: Option<Cause>) {
+    let mut history = HISTORY.lock().unwrap();
+    if let Some(run) = history.get_mut(name).and_then(|runs| runs.back_mut())
+        && run.stop.is_none()
+    {
+        run.stop = Some(Local::now());
+        run.exit_code = Some(exit_code);
+        run.success = success;
+        run.stopped_by = stopped_by;
+        journal::write(name, journal::MANAGER, Some(run.pid), &run.outcome());
+    }
+}
+
+/// Returns the recorded runs of `name`, oldest first.
+pub fn runs(name: &str) -> Vec<Run> {
+    HISTORY.lock().unwrap()
+        .get(name)
+        .map(|runs| runs.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn last_failure(name: &str) -> Option<Run> {
+    HISTORY.lock().unwrap()
+        .get(name)
+        .and_then(|runs| runs.iter().rev().find(|run| run.is_failure()).cloned())
+}
@@ -0,0 +1,181 @@
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::time::{Duration, Instant};
+use std::{io, mem, thread};
+use windows::Win32::Foundation as Win32Foundation;
+use windows::Win32::System::JobObjects as Win32JobObjects;
+use windows::Win32::System::Threading as Win32Threading;
+use windows::Win32::System::Diagnostics::ToolHelp as Win32ToolHelp;
+
+const MAX_MEMBERS: usize = 1024;
+
+/// A Job Object holding a service's main process and every process it
+/// starts, so the whole tree can be listed and terminated together.
+///
+/// A job lives only as long as the manager holds it: its processes do not
+/// keep it open. A restarted manager puts an adopted process tree into a
+/// new job, found through `process_tree`.
+pub struct Job {
+    handle: OwnedHandle,
+}
+
+impl Job {
+    pub fn create() -> io::Result<Self> {
+        unsafe {
+            let handle = Win32JobObjects::CreateJobObjectW(None, None)?;
+            Ok(Job { handle: OwnedHandle::from_raw_handle(handle.0) })
+        }
+    }
+
+    fn raw_handle(&self) -> Win32Foundation::HANDLE {
+        Win32Foundation::HANDLE(self.handle.as_raw_handle())
+    }
+
+    pub fn assign(&self, process: Win32Foundation::HANDLE) -> io::Result<()> {
+        unsafe {
+            Win32JobObjects::AssignProcessToJobObject(self.raw_handle(), process)?;
+        }
+        Ok(())
+    }
+
+    /// Puts the running process `pid` into the job.
+    pub fn assign_pid(&self, pid: u32) -> io::Result<()> {
+        unsafe {
+            let process = Win32Threading::OpenProcess(
+                Win32Threading::PROCESS_SET_QUOTA | Win32Threading::PROCESS_TERMINATE,
+                false,
+                pid,
+            )?;
+            let process = OwnedHandle::from_raw_handle(process.0);
+            self.assign(Win32Foundation::HANDLE(process.as_raw_handle()))
+        }
+    }
+
+    /// Returns the PIDs of the processes currently in the job.
+    pub fn members(&self) -> io::Result<Vec<u32>> {
+        #[repr(C)]
+        struct ProcessIdList {
+            assigned: u32,
+            in_list: u32,
+            ids: [usize; MAX_MEMBERS],
+        }
+
+        let mut list = ProcessIdList { assigned: 0, in_list: 0, ids: [0; MAX_MEMBERS] };
+        unsafe {
+            Win32JobObjects::QueryInformationJobObject(
+                Some(self.raw_handle()),
+                Win32JobObjects::JobObjectBasicProcessIdList,
+                &mut list as *mut ProcessIdList as *mut _,
+                mem::size_of::<ProcessIdList>() as u32,
+                None,
+            )?;
+        }
+        Ok(list.ids[..list.in_list as usize].iter().map(|&pid| pid as u32).collect())
+    }
+
+    /// Polls until the job has no processes left, returning `false` if
+    /// `deadline` passes first.
+    pub fn wait_empty(&self, deadline: Instant) -> bool {
+        loop {
+            match self.members() {
+                Ok(members) if members.is_empty() => return true,
+                Err(_) => return false,
+                Ok(_) => {}
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    pub fn terminate(&self, exit_code: u32) -> io::Result<()> {
+        unsafe {
+            Win32JobObjects::TerminateJobObject(self.raw_handle(), exit_code)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `pid` and the running processes it started, directly or through
+/// its running children, from the parent PIDs recorded by Windows. A child
+/// whose parent has already exited cannot be found.
+pub fn process_tree(pid: u32) -> io::Result<Vec<u32>> {
+    let mut parents = Vec::new();
+    unsafe {
+        let snapshot = Win32ToolHelp::CreateToolhelp32Snapshot(Win32ToolHelp::TH32CS_SNAPPROCESS, 0)?;
+        let owned_snapshot = OwnedHandle::from_raw_handle(snapshot.0);
+        let snapshot = Win32Foundation::HANDLE(owned_snapshot.as_raw_handle());
+
+        let mut entry = Win32ToolHelp::PROCESSENTRY32W {
+            dwSize: mem::size_of::<Win32ToolHelp::PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut result = Win32ToolHelp::Process32FirstW(snapshot, &mut entry);
+        while result.is_ok() {
+            parents.push((entry.th32ProcessID, entry.th32ParentProcessID));
+            result = Win32ToolHelp::Process32NextW(snapshot, &mut entry);
+        }
+    }
+
+    let mut tree = vec![pid];
+    let mut index = 0;
+    while let Some(&parent) = tree.get(index) {
+        index += 1;
+        let Some(parent_created) = creation_time(parent) else {
+            continue;
+        };
+        for &(child, _) in parents.iter().filter(|&&(child, ppid)| ppid == parent && child != parent) {
+            // The parent PID may have been reused by a process that started
+            // after the child.
+            if !tree.contains(&child) && creation_time(child).is_some_and(|created| created >= parent_created) {
+                tree.push(child);
+            }
+        }
+    }
+    Ok(tree)
+}
+
+fn creation_time(pid: u32) -> Option<u64> {
+    unsafe {
+        let process = Win32Threading::OpenProcess(Win32Threading::PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let process = OwnedHandle::from_raw_handle(process.0);
+        let mut creation = Win32Foundation::FILETIME::default();
+        let mut exit = Win32Foundation::FILETIME::default();
+        let mut kernel = Win32Foundation::FILETIME::default();
+        let mut user = Win32Foundation::FILETIME::default();
+        Win32Threading::GetProcessTimes(
+            Win32Foundation::HANDLE(process.as_raw_handle()),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        ).ok()?;
+        Some(((creation.dwHighDateTime as u64) << 32) | creation.dwLowDateTime as u64)
+    }
+}
+
+/// Resumes every thread of a process created with `CREATE_SUSPENDED`.
+pub fn resume_threads(pid: u32) -> io::Result<()> {
+    unsafe {
+        let snapshot = Win32ToolHelp::CreateToolhelp32Snapshot(Win32ToolHelp::TH32CS_SNAPTHREAD, 0)?;
+        let owned_snapshot = OwnedHandle::from_raw_handle(snapshot.0);
+        let snapshot = Win32Foundation::HANDLE(owned_snapshot.as_raw_handle());
+
+        let mut entry = Win32ToolHelp::THREADENTRY32 {
+            dwSize: mem::size_of::<Win32ToolHelp::THREADENTRY32>() as u32,
+            ..Default::default()
+        };
+        let mut result = Win32ToolHelp::Thread32First(snapshot, &mut entry);
+        while result.is_ok() {
+            if entry.th32OwnerProcessID == pid {
+                let thread = Win32Threading::OpenThread(Win32Threading::THREAD_SUSPEND_RESUME, false, entry.th32ThreadID)?;
+                let thread = OwnedHandle::from_raw_handle(thread.0);
+                if Win32Threading::ResumeThread(Win32Foundation::HANDLE(thread.as_raw_handle())) == u32::MAX {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            result = Win32ToolHelp::Thread32Next(snapshot, &mut entry);
+        }
+    }
+    Ok(())
+}
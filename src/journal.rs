@@ -0,0 +1,169 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeDelta};
+use crate::config::{self, OutputMode, Settings};
+use crate::output::{self, LogFile, Rotation};
+
+/// Stream of records written by the manager itself.
+pub const MANAGER: &str = "manager";
+
+/// The open journal file, `None` until the first record is written.
+static JOURNAL: Lazy<Mutex<Option<LogFile>>> = Lazy::new(|| Mutex::new(None));
+
+/// Receivers of every record written from now on, for `logs -f`.
+static SUBSCRIBERS: Lazy<Mutex<Vec<Sender<Record>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// One line of service output or one manager event.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub ts: DateTime<Local>,
+    pub unit: String,
+    /// `stdout`, `stderr` or `manager`.
+    pub stream: String,
+    pub pid: Option<u32>,
+    pub message: String,
+}
+
+impl Record {
+    /// One line as printed by `logs`, e.g.
+    /// `2025-01-01 08:00:00.123 example[1234] stderr: message`.
+    pub fn line(&self) -> String {
+        let pid = self.pid.map(|pid| format!("[{}]", pid)).unwrap_or_default();
+        format!("{} {}{} {}: {}", self.ts.format("%Y-%m-%d %H:%M:%S%.3f"), self.unit, pid, self.stream, self.message)
+    }
+}
+
+/// Which records `query` returns.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+    /// Only records whose message contains this text.
+    pub grep: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, unit: &str, record: &Record) -> bool {
+        record.unit == unit
+            && self.since.is_none_or(|since| record.ts >= since)
+            && self.until.is_none_or(|until| record.ts <= until)
+            && self.grep.as_ref().is_none_or(|grep| record.message.contains(grep.as_str()))
+    }
+}
+
+fn path() -> io::Result<PathBuf> {
+    let dir = std::env::current_exe()?.with_file_name("log");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join("journal.jsonl"))
+}
+
+/// Applies the journal rotation of `settings` to the open journal file.
+pub fn configure(settings: &Settings) {
+    if let Some(file) = JOURNAL.lock().unwrap().as_mut() {
+        file.set_rotation(Some(rotation(settings)));
+    }
+}
+
+fn rotation(settings: &Settings) -> Rotation {
+    Rotation {
+        max_size: Some(settings.journal_max_size),
+        max_age: None,
+        max_files: settings.journal_max_files,
+        compress: false,
+    }
+}
+
+/// Appends a record to the journal if it is enabled.
+pub fn write(unit: &str, stream: &str, pid: Option<u32>, message: &str) {
+    if !config::journal_enabled() {
+        return;
+    }
+    let record = Record {
+        ts: Local::now(),
+        unit: unit.to_string(),
+        stream: stream.to_string(),
+        pid,
+        message: message.to_string(),
+    };
+
+    if let Err(e) = append(&mut JOURNAL.lock().unwrap(), &record) {
+        // Debug level, as this runs for every line of service output.
+        log::debug!("Failed to write to the journal: {}", e);
+    }
+
+    // Receivers that were dropped are forgotten.
+    SUBSCRIBERS.lock().unwrap().retain(|subscriber| subscriber.send(record.clone()).is_ok());
+}
+
+/// Returns a receiver of every record written after this call.
+pub fn subscribe() -> Receiver<Record> {
+    let (sender, receiver) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+fn append(journal: &mut Option<LogFile>, record: &Record) -> io::Result<()> {
+    let file = match journal {
+        Some(file) => file,
+        None => journal.insert(LogFile::open(path()?, &OutputMode::Append, Some(&rotation(&config::settings())))?),
+    };
+    let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Returns the records of `unit` matching `filter`, oldest first.
+pub fn query(unit: &str, filter: &Filter) -> io::Result<Vec<Record>> {
+    let path = path()?;
+    let max_files = config::settings().journal_max_files;
+    let mut files: Vec<PathBuf> = (1..=max_files).rev()
+        .map(|index| output::numbered(&path, index, false))
+        .collect();
+    files.push(path);
+
+    let mut records = Vec::new();
+    for file in files {
+        let file = match File::open(&file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            // Skip lines that were cut off or are not records.
+            if let Ok(record) = serde_json::from_str::<Record>(&line?)
+                && filter.matches(unit, &record)
+            {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Parses `2025-01-01 08:00:00`, `2025-01-01 08:00`, `2025-01-01`, or a time
+/// relative to now such as `-30s`, `-10m`, `-2h`, `-1d`.
+pub fn parse_time(value: &str) -> Option<DateTime<Local>> {
+    if let Some(relative) = value.strip_prefix('-') {
+        let unit = relative.chars().last()?;
+        let number: i64 = relative[..relative.len() - unit.len_utf8()].parse().ok()?;
+        let delta = match unit {
+            's' => TimeDelta::try_seconds(number)?,
+            'm' => TimeDelta::try_minutes(number)?,
+            'h' => TimeDelta::try_hours(number)?,
+            'd' => TimeDelta::try_days(number)?,
+            _ => return None,
+        };
+        return Some(Local::now() - delta);
+    }
+
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default()))
+        .ok()?;
+    naive.and_local_timezone(Local).earliest()
+}
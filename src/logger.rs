@@ -0,0 +1,170 @@
+use once_cell::sync::Lazy;
+use std::sync::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use chrono::{Local, SecondsFormat};
+use log::{LevelFilter, Metadata};
+use log::kv::{self, Key, VisitValue};
+use serde_json::{Map, Value};
+use crate::config::{OutputMode, Settings};
+use crate::output::{LogFile, Rotation};
+
+/// Key-values of a log call copied into JSON lines, e.g.
+/// `log::info!(unit = name, event = "started", pid = pid; "...")`.
+const CONTEXT_KEYS: [&str; 4] = ["unit", "event", "pid", "exit_code"];
+
+/// Levels in effect, from the settings or the `log-level` command.
+static LEVELS: Lazy<RwLock<Levels>> = Lazy::new(|| RwLock::new(Levels::default()));
+
+/// The manager's log file, `None` if it could not be opened.
+static FILE: Lazy<Mutex<Option<LogFile>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug)]
+struct Levels {
+    global: LevelFilter,
+    /// By module name without the crate prefix, e.g. `health`.
+    modules: HashMap<String, LevelFilter>,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels { global: LevelFilter::Info, modules: HashMap::new() }
+    }
+}
+
+impl Levels {
+    /// The level of the most specific module `target` is in.
+    fn of(&self, target: &str) -> LevelFilter {
+        let module = target.strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::")).unwrap_or(target);
+        self.modules.iter()
+            .filter(|(name, _)| module == name.as_str() || module.starts_with(&format!("{}::", name)))
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.global, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules.values().copied().fold(self.global, Ord::max)
+    }
+}
+
+/// Whether a record is written, for `fern::Dispatch::filter`.
+pub fn enabled(metadata: &Metadata) -> bool {
+    metadata.level() <= LEVELS.read().unwrap().of(metadata.target())
+}
+
+/// Sets the level of `module`, or the global one, until the settings are
+/// applied again.
+pub fn set_level(module: Option<&str>, level: LevelFilter) {
+    let mut levels = LEVELS.write().unwrap();
+    match module {
+        Some(module) => {
+            levels.modules.insert(module.to_string(), level);
+        }
+        None => levels.global = level,
+    }
+    log::set_max_level(levels.max());
+}
+
+/// Applies the log levels and rotation of `settings`.
+pub fn configure(settings: &Settings) {
+    {
+        let mut levels = LEVELS.write().unwrap();
+        levels.global = settings.log_level;
+        levels.modules = settings.log_modules.clone();
+        log::set_max_level(levels.max());
+    }
+    if let Some(file) = FILE.lock().unwrap().as_mut() {
+        file.set_rotation(Some(rotation(settings)));
+    }
+}
+
+fn rotation(settings: &Settings) -> Rotation {
+    Rotation {
+        max_size: Some(settings.log_max_size),
+        max_age: None,
+        max_files: settings.log_max_files,
+        compress: false,
+    }
+}
+
+/// Opens the log file at `path` and returns the writer for `fern`.
+pub fn open(path: PathBuf) -> io::Result<Writer> {
+    let file = LogFile::open(path, &OutputMode::Append, Some(&rotation(&Settings::default())))?;
+    *FILE.lock().unwrap() = Some(file);
+    Ok(Writer(Vec::new()))
+}
+
+/// Writes to the log file, one record at a time so a record is never split
+/// by a rotation. `fern` flushes after every record.
+pub struct Writer(Vec<u8>);
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = match FILE.lock().unwrap().as_mut() {
+            Some(file) => file.write_all(&self.0),
+            None => Ok(()),
+        };
+        self.0.clear();
+        result
+    }
+}
+
+/// Renders a record as one JSON object. Context keys the call did not set
+/// are `null`, so every line has the same fields.
+pub fn json_line(message: &fmt::Arguments, record: &log::Record) -> String {
+    let mut line = Map::new();
+    line.insert("ts".to_string(), Local::now().to_rfc3339_opts(SecondsFormat::Millis, false).into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("module".to_string(), record.module_path().unwrap_or(record.target()).into());
+    for key in CONTEXT_KEYS {
+        let mut value = JsonValue(Value::Null);
+        if let Some(kv) = record.key_values().get(Key::from_str(key)) {
+            kv.visit(&mut value).ok();
+        }
+        line.insert(key.to_string(), value.0);
+    }
+    line.insert("message".to_string(), message.to_string().into());
+    Value::Object(line).to_string()
+}
+
+/// A log value converted to JSON, keeping numbers and `None` as such.
+struct JsonValue(Value);
+
+impl<'v> VisitValue<'v> for &mut JsonValue {
+    fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
@@ -3,12 +3,26 @@ mod process;
 mod server;
 mod client;
 mod pipe;
+mod unit_file;
+mod condition;
+mod job;
+mod history;
+mod unit_state;
+mod notify;
+mod health;
+mod probe;
+mod output;
+mod journal;
+mod logger;
+mod resources;
+mod restart;
 
 use std::{
     mem,
     fmt::Display,
     thread::{self, sleep},
     time::{Duration, Instant},
+    sync::atomic::{AtomicBool, Ordering},
     ffi::{c_void, OsStr, OsString},
 };
 
@@ -114,8 +128,75 @@ enum Commands {
         #[arg(index = 1)]
         name: String,
     },
+    #[doc = "List the services and their state"]
+    List {
+        #[arg(long)]
+        #[doc = "Show CPU usage since the previous listing, CPU time, memory, processes, threads, handles and uptime, busiest first"]
+        resources: bool,
+    },
+    #[doc = "Show the recent runs of a service"]
+    History {
+        #[arg(index = 1)]
+        name: String,
+    },
+    #[doc = "Show the journal of a service"]
+    Logs {
+        #[arg(index = 1)]
+        name: String,
+
+        #[arg(short = 'n', long)]
+        #[doc = "Show only the last N records"]
+        lines: Option<usize>,
+
+        #[arg(long)]
+        #[doc = "Show records from this time on, e.g. `2025-01-01 08:00`, `2025-01-01` or `-10m`"]
+        since: Option<String>,
+
+        #[arg(long)]
+        #[doc = "Show records up to this time"]
+        until: Option<String>,
+
+        #[arg(long)]
+        #[doc = "Show only records containing this text"]
+        grep: Option<String>,
+
+        #[arg(short = 'f', long)]
+        #[doc = "Keep printing new records as they are written"]
+        follow: bool,
+    },
+    #[doc = "Import a systemd .service file into the configs directory"]
+    Import {
+        #[arg(index = 1)]
+        path: String,
+
+        #[arg(long)]
+        #[doc = "Name of the imported service, defaults to the file name"]
+        name: Option<String>,
+    },
+    #[doc = "Print a service in another service manager's format, as loaded by the manager, or as in the files on disk if it is not running"]
+    Export {
+        #[arg(index = 1)]
+        name: String,
+
+        #[arg(long, value_enum, default_value = "systemd")]
+        format: unit_file::ExportFormat,
+    },
     #[doc = "Reload all service configurations"]
-    ReloadConfig,
+    ReloadConfig {
+        #[arg(long)]
+        #[doc = "Restart changed running services, stop removed ones and start new Startup services"]
+        apply: bool,
+    },
+    #[doc = "Change the manager log level until the next reload-config"]
+    LogLevel {
+        #[arg(index = 1)]
+        #[doc = "off, error, warn, info, debug or trace"]
+        level: log::LevelFilter,
+
+        #[arg(long)]
+        #[doc = "Only change the level of this module, e.g. health"]
+        module: Option<String>,
+    },
 
 }
 
@@ -256,6 +337,13 @@ fn main() {
                     return;
                 }
             },
+            Commands::Import { path, name } => {
+                match unit_file::import(std::path::Path::new(&path), name.as_deref()) {
+                    Ok(report) => println!("{}", report),
+                    Err(e) => println!("Failed to import `{}`: {}", path, e),
+                }
+                return;
+            },
             _ => {}
         }
     }
@@ -268,7 +356,20 @@ fn main() {
             });
 
     if mutex.is_holding() {
-        eprintln!("Service is not running.");
+        match cli.command {
+            // Without a manager to ask, the files on disk are rendered.
+            Some(Commands::Export { ref name, ref format }) => {
+                match config::load().and_then(|()| unit_file::export(name, format)) {
+                    Ok(rendered) => print!("{}", rendered),
+                    Err(e) => println!("Failed to export `{}`: {}", name, e),
+                }
+            }
+            _ => eprintln!("Service is not running."),
+        }
+    } else if let Some(Commands::Logs { .. }) = cli.command {
+        if let Err(e) = client::stream(&cli) {
+            eprintln!("{}", e);
+        }
     } else {
         println!("{}", client::run(&cli));
     }
@@ -276,19 +377,21 @@ fn main() {
 
 fn setup_logger() -> Result<()> {
     fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
+        .format(|out, message, record| match config::log_format() {
+            config::LogFormat::Text => out.finish(format_args!(
                 "[{}][{}][{}:{}] {}",
                 chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
                 record.level(),
                 record.file().unwrap_or_default(),
                 record.line().unwrap_or_default(),
                 message
-            ))
+            )),
+            config::LogFormat::Json => out.finish(format_args!("{}", logger::json_line(message, record))),
         })
+        .filter(logger::enabled)
         .chain(
-            fern::log_file(std::env::current_exe()?
-                .with_file_name(format!("{}.log", APP_NAME)))?
+            Box::new(logger::open(std::env::current_exe()?
+                .with_file_name(format!("{}.log", APP_NAME)))?) as Box<dyn std::io::Write + Send>
         ).apply().map_err(|e| {
             Error::String(format!("Failed to setup logger: {}", e))
         })?;
@@ -428,13 +531,15 @@ fn install() -> Result<()> {
         account_password: None,
     };
 
-    ServiceManager::local_computer(
+    let service = ServiceManager::local_computer(
         None::<&str>, 
         ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE
     )?.create_service(
         &service_info, 
         ServiceAccess::CHANGE_CONFIG
-    )?.set_description("A Linux-like service manager")?;
+    )?;
+    service.set_description("A Linux-like service manager")?;
+    service.set_preshutdown_timeout(PRESHUTDOWN_TIMEOUT)?;
 
     Ok(())
 }
@@ -540,6 +645,51 @@ fn unregister() -> Result<()> {
 
 
 static STATUS_HDL: OnceCell<ServiceStatusHandle> = OnceCell::new();
+
+static SERVICE_STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// How often the progress of a stop is reported to the service control manager.
+const STOP_PENDING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the system waits for the manager to stop its units when it
+/// shuts down, if they take that long.
+const PRESHUTDOWN_TIMEOUT: Duration = Duration::from_secs(180);
+
+fn set_service_state(current_state: ServiceState, checkpoint: u32, wait_hint: Duration) {
+    if let Some(status_handle) = STATUS_HDL.get() {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted: ServiceControlAccept::empty(),
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint,
+            wait_hint,
+            process_id: None,
+        }).unwrap_or_else(|e| {
+            log::error!("Failed to set service status: {:?}", e);
+        });
+    }
+}
+
+/// Runs `server::stop` on a thread of its own, reporting `StopPending` with
+/// a new checkpoint every `STOP_PENDING_INTERVAL` until it is done.
+fn stop_in_background() {
+    if SERVICE_STOPPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(|| {
+        let stopping = thread::spawn(server::stop);
+        let mut checkpoint = 1;
+        while !stopping.is_finished() {
+            set_service_state(ServiceState::StopPending, checkpoint, STOP_PENDING_INTERVAL * 3);
+            checkpoint += 1;
+            sleep(STOP_PENDING_INTERVAL);
+        }
+        stopping.join().ok();
+        set_service_state(ServiceState::Stopped, 0, Duration::default());
+        log::info!("Service stopped successfully.");
+    });
+}
 define_windows_service!(ffi_service_main, service_main);
 fn service_main(arguments: Vec<std::ffi::OsString>) {
     log::info!("Service started with arguments: {:?}", arguments);
@@ -552,24 +702,9 @@ fn run_service() ->  Result<()> {
 
     let event_handler = move |control_event| -> ServiceControlHandlerResult {
         match control_event {
-            ServiceControl::Stop => {
-                server::stop();
-                if let Some(status_handle) = STATUS_HDL.get() {
-                    status_handle.set_service_status(ServiceStatus {
-                        service_type: ServiceType::OWN_PROCESS,
-                        current_state: ServiceState::Stopped,
-                        controls_accepted: ServiceControlAccept::empty(),
-                        exit_code: ServiceExitCode::Win32(0),
-                        checkpoint: 0,
-                        wait_hint: Duration::default(),
-                        process_id: None,
-                    }).unwrap_or_else(|e| {
-                        log::error!("Failed to set service status: {:?}", e);
-                    });
-                    log::info!("Service stopped successfully.");
-                } else {
-                    log::info!("Service stopped, but status handle is missing.");
-                }
+            // The handler must return quickly, stopping every unit may not.
+            ServiceControl::Stop | ServiceControl::Preshutdown | ServiceControl::Shutdown => {
+                stop_in_background();
                 ServiceControlHandlerResult::NoError
             },
             ServiceControl::Interrogate => {
@@ -624,8 +759,9 @@ fn run_service() ->  Result<()> {
             service_type: ServiceType::OWN_PROCESS,
             // The new state
             current_state: ServiceState::Running,
-            // Accept stop events when running
-            controls_accepted: ServiceControlAccept::STOP,
+            // Accept stop events when running, and stop the units before
+            // the system shuts down
+            controls_accepted: ServiceControlAccept::STOP | ServiceControlAccept::PRESHUTDOWN | ServiceControlAccept::SHUTDOWN,
             // Used to report an error when starting or stopping only, otherwise must be zero
             exit_code: ServiceExitCode::Win32(0),
             // Only used for pending states, otherwise must be zero
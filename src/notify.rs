@@ -0,0 +1,169 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::{io, thread};
+use chrono::{DateTime, Local};
+use windows::core::HSTRING;
+use windows::Win32::Foundation as Win32Foundation;
+use windows::Win32::Storage::FileSystem as Win32FileSystem;
+use windows::Win32::System::Pipes as Win32Pipes;
+
+use crate::{APP_NAME, process};
+use crate::unit_state::{self, ActiveState, SubState};
+
+/// Environment variable holding the notify pipe of a unit, named after
+/// systemd's `NOTIFY_SOCKET`.
+pub const ENV_NAME: &str = "NOTIFY_SOCKET";
+
+/// Size of the pipe buffer, which bounds the size of a single notification.
+const BUFFER_SIZE: usize = 4096;
+
+static NOTIFICATIONS: Lazy<Mutex<HashMap<String, Notifications>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Units whose notify pipe is being listened on.
+static ENDPOINTS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// What the current run of a unit has reported about itself.
+#[derive(Debug, Clone, Default)]
+pub struct Notifications {
+    /// Set by `READY=1`, cleared by `RELOADING=1`.
+    pub ready: bool,
+    /// The last `STATUS=` line.
+    pub status: Option<String>,
+    /// When the last `WATCHDOG=1` arrived.
+    pub watchdog: Option<DateTime<Local>>,
+}
+
+fn address(name: &str) -> String {
+    format!(r"\\.\pipe\{}-notify-{}", APP_NAME, name)
+}
+
+fn create_instance(address: &str) -> io::Result<OwnedHandle> {
+    unsafe {
+        let handle = Win32Pipes::CreateNamedPipeW(
+            &HSTRING::from(address),
+            Win32FileSystem::PIPE_ACCESS_INBOUND,
+            Win32Pipes::PIPE_TYPE_MESSAGE | Win32Pipes::PIPE_READMODE_MESSAGE | Win32Pipes::PIPE_WAIT,
+            Win32Pipes::PIPE_UNLIMITED_INSTANCES,
+            0,
+            BUFFER_SIZE as u32,
+            0,
+            None,
+        );
+        if handle.is_invalid() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedHandle::from_raw_handle(handle.0))
+    }
+}
+
+/// Clears what the previous run of `name` reported and makes sure its notify
+/// pipe is listened on. Returns the pipe address to pass to the process.
+///
+/// The listener lives as long as the manager and is reused across restarts.
+pub fn open(name: &str) -> io::Result<String> {
+    NOTIFICATIONS.lock().unwrap().insert(name.to_string(), Notifications::default());
+
+    let address = address(name);
+    let mut endpoints = ENDPOINTS.lock().unwrap();
+    if !endpoints.contains(name) {
+        // The first instance is created here so the process can connect as
+        // soon as it starts.
+        let pipe = create_instance(&address)?;
+        endpoints.insert(name.to_string());
+        let (thread_name, thread_address) = (name.to_string(), address.clone());
+        thread::spawn(move || listen(&thread_name, &thread_address, pipe));
+    }
+    Ok(address)
+}
+
+fn listen(name: &str, address: &str, mut pipe: OwnedHandle) {
+    loop {
+        let connected = unsafe {
+            match Win32Pipes::ConnectNamedPipe(Win32Foundation::HANDLE(pipe.as_raw_handle()), None) {
+                Ok(()) => true,
+                Err(e) => e.code() == Win32Foundation::ERROR_PIPE_CONNECTED.to_hresult(),
+            }
+        };
+
+        // Create the next instance before reading so another connection
+        // finds the pipe busy rather than missing.
+        let next = match create_instance(address) {
+            Ok(next) => next,
+            Err(e) => {
+                log::error!(unit = name, event = "notify_failed"; "Failed to listen for notifications of service {}: {}", name, e);
+                ENDPOINTS.lock().unwrap().remove(name);
+                return;
+            }
+        };
+
+        if connected {
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            loop {
+                let mut bytes_read = 0u32;
+                let result = unsafe {
+                    Win32FileSystem::ReadFile(
+                        Win32Foundation::HANDLE(pipe.as_raw_handle()),
+                        Some(&mut buffer),
+                        Some(&mut bytes_read),
+                        None,
+                    )
+                };
+                if result.is_err() {
+                    break;
+                }
+                handle(name, &String::from_utf8_lossy(&buffer[..bytes_read as usize]));
+            }
+            unsafe {
+                Win32Pipes::DisconnectNamedPipe(Win32Foundation::HANDLE(pipe.as_raw_handle())).ok();
+            }
+        }
+        pipe = next;
+    }
+}
+
+/// Applies the newline separated `KEY=VALUE` assignments of one notification.
+fn handle(name: &str, message: &str) {
+    for line in message.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match (key, value) {
+            ("READY", "1") => {
+                update(name, |notifications| notifications.ready = true);
+                if unit_state::get(name).active == ActiveState::Reloading {
+                    unit_state::set(name, ActiveState::Active, SubState::Running, Some("reloaded".to_string()));
+                }
+            }
+            ("RELOADING", "1") => {
+                update(name, |notifications| notifications.ready = false);
+                unit_state::set(name, ActiveState::Reloading, SubState::Reload, Some("reported by service".to_string()));
+            }
+            ("STOPPING", "1") => {
+                unit_state::set(name, ActiveState::Deactivating, SubState::Stop, Some("reported by service".to_string()));
+            }
+            ("WATCHDOG", "1") => update(name, |notifications| notifications.watchdog = Some(Local::now())),
+            ("STATUS", status) => update(name, |notifications| notifications.status = Some(status.to_string())),
+            ("MAINPID", pid) => match pid.parse::<u32>() {
+                Ok(pid) => match process::set_main_pid(name, pid) {
+                    Ok(()) => log::info!(unit = name, event = "main_pid_changed", pid = pid; "Service {} changed its main process to pid {}.", name, pid),
+                    Err(e) => log::warn!(unit = name, event = "main_pid_rejected", pid = pid; "Service {} reported MAINPID={}, ignored: {}", name, pid, e),
+                },
+                Err(_) => log::warn!(unit = name, event = "main_pid_rejected"; "Service {} reported an invalid MAINPID={}", name, pid),
+            },
+            _ => log::debug!(unit = name, event = "unknown_notification"; "Service {} sent an unknown notification {}", name, line),
+        }
+    }
+}
+
+fn update<F>(name: &str, f: F)
+where
+    F: FnOnce(&mut Notifications),
+{
+    f(NOTIFICATIONS.lock().unwrap().entry(name.to_string()).or_default());
+}
+
+pub fn get(name: &str) -> Notifications {
+    NOTIFICATIONS.lock().unwrap().get(name).cloned().unwrap_or_default()
+}
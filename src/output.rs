@@ -0,0 +1,457 @@
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, PipeReader, PipeWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use chrono::Local;
+use crate::config::{self, Output, OutputMode, Service};
+use crate::journal::{self, Record};
+
+/// Rotated files kept when only a size or age limit is set.
+const DEFAULT_MAX_FILES: usize = 5;
+
+/// Lines of output kept per unit for `status`.
+const RECENT_LINES: usize = 10;
+
+/// How often log files written by a child are read for new lines.
+const TAIL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The latest captured lines of every unit, across its runs.
+static RECENT: Lazy<Mutex<HashMap<String, VecDeque<Record>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Set to stop tailing the log files of the current run of each unit.
+static TAILS: Lazy<Mutex<HashMap<String, Arc<AtomicBool>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// When the log file of a stream is rotated and what is kept.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_files: usize,
+    pub compress: bool,
+}
+
+impl Rotation {
+    /// Returns the rotation configured for `service`, `None` if it sets
+    /// neither a size nor an age limit.
+    pub fn of(service: &Service) -> Option<Self> {
+        if service.log_max_size.is_none() && service.log_max_age_sec.is_none() {
+            return None;
+        }
+        Some(Rotation {
+            max_size: service.log_max_size,
+            max_age: service.log_max_age_sec.map(Duration::from_secs),
+            max_files: service.log_max_files.unwrap_or(DEFAULT_MAX_FILES),
+            compress: service.log_compress.unwrap_or(false),
+        })
+    }
+}
+
+/// The log file of one output stream of a run. With a rotation it is moved
+/// to `<file>.1`, `<file>.2`, ... once it grows past the size limit or gets
+/// older than the age limit.
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    rotation: Option<Rotation>,
+}
+
+impl LogFile {
+    /// Opens `path` for a new run. The output of the previous run is kept
+    /// with `append`, rotated away if there is a rotation, or truncated.
+    pub fn open(path: PathBuf, mode: &OutputMode, rotation: Option<&Rotation>) -> io::Result<Self> {
+        let file = match (mode, rotation) {
+            (OutputMode::Append, _) => OpenOptions::new().create(true).append(true).open(&path)?,
+            (OutputMode::Truncate, Some(rotation)) => {
+                if fs::metadata(&path).is_ok_and(|meta| meta.len() > 0) {
+                    rotate(&path, rotation)?;
+                }
+                File::create(&path)?
+            }
+            (OutputMode::Truncate, None) => File::create(&path)?,
+        };
+        Ok(LogFile {
+            size: file.metadata()?.len(),
+            file,
+            path,
+            opened: Instant::now(),
+            rotation: rotation.cloned(),
+        })
+    }
+
+    /// The file itself, for a child that writes to it directly.
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    /// Changes the rotation from the next write on.
+    pub fn set_rotation(&mut self, rotation: Option<Rotation>) {
+        self.rotation = rotation;
+    }
+
+    fn is_due(&self, incoming: usize) -> bool {
+        let Some(rotation) = &self.rotation else {
+            return false;
+        };
+        if self.size == 0 {
+            return false;
+        }
+        rotation.max_size.is_some_and(|max_size| self.size + incoming as u64 > max_size)
+            || rotation.max_age.is_some_and(|max_age| self.opened.elapsed() >= max_age)
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.is_due(data.len())
+            && let Some(rotation) = &self.rotation
+        {
+            rotate(&self.path, rotation)?;
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            self.opened = Instant::now();
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Whether the output of `service` has to pass through the manager rather
+/// than being written by the child itself.
+pub fn is_captured(service: &Service) -> bool {
+    Rotation::of(service).is_some() || service.log_timestamps == Some(true)
+}
+
+pub fn numbered(path: &Path, index: usize, compressed: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+/// Shifts `<file>.1` ... to `<file>.2` ..., dropping the oldest, and moves
+/// `path` to `<file>.1`, compressing it if configured.
+fn rotate(path: &Path, rotation: &Rotation) -> io::Result<()> {
+    if rotation.max_files == 0 {
+        return fs::remove_file(path);
+    }
+
+    for compressed in [false, true] {
+        fs::remove_file(numbered(path, rotation.max_files, compressed)).ok();
+    }
+    for index in (1..rotation.max_files).rev() {
+        for compressed in [false, true] {
+            let from = numbered(path, index, compressed);
+            if from.exists() {
+                fs::rename(&from, numbered(path, index + 1, compressed))?;
+            }
+        }
+    }
+
+    let rotated = numbered(path, 1, false);
+    fs::rename(path, &rotated)?;
+    if rotation.compress {
+        let mut encoder = GzEncoder::new(File::create(numbered(path, 1, true))?, Compression::default());
+        io::copy(&mut File::open(&rotated)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(&rotated)?;
+    }
+    Ok(())
+}
+
+/// What a child gets as one of its output handles.
+enum ChildOutput {
+    File(File),
+    Pipe(PipeWriter),
+    Null,
+    Inherit,
+}
+
+impl ChildOutput {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            ChildOutput::File(file) => ChildOutput::File(file.try_clone()?),
+            ChildOutput::Pipe(pipe) => ChildOutput::Pipe(pipe.try_clone()?),
+            ChildOutput::Null => ChildOutput::Null,
+            ChildOutput::Inherit => ChildOutput::Inherit,
+        })
+    }
+}
+
+impl From<ChildOutput> for Stdio {
+    fn from(output: ChildOutput) -> Self {
+        match output {
+            ChildOutput::File(file) => file.into(),
+            ChildOutput::Pipe(pipe) => pipe.into(),
+            ChildOutput::Null => Stdio::null(),
+            ChildOutput::Inherit => Stdio::inherit(),
+        }
+    }
+}
+
+/// An output stream of a run that passes through the manager.
+struct Capture {
+    source: PipeReader,
+    stream: &'static str,
+    /// `None` for output that only goes to the journal.
+    file: Option<LogFile>,
+}
+
+/// A log file written by the child itself, read back for the journal and
+/// the recent lines.
+struct Tail {
+    path: PathBuf,
+    stream: &'static str,
+    /// Bytes of the file read so far, starting after earlier runs.
+    offset: u64,
+    /// The last line, until it is complete.
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    fn new(path: PathBuf, stream: &'static str, offset: u64) -> Self {
+        Tail { path, stream, offset, partial: Vec::new() }
+    }
+
+    /// Passes the lines written since the last call to `sink`. An incomplete
+    /// last line is held back unless `flush`.
+    fn read(&mut self, sink: &mut Sink, flush: bool) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        if file.metadata()?.len() < self.offset {
+            // Truncated by someone else, start over.
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        self.offset += file.read_to_end(&mut self.partial)? as u64;
+
+        let mut start = 0;
+        while let Some(end) = self.partial[start..].iter().position(|&byte| byte == b'\n') {
+            sink.write_line(&self.partial[start..=start + end])?;
+            start += end + 1;
+        }
+        self.partial.drain(..start);
+        if flush && !self.partial.is_empty() {
+            sink.write_line(&self.partial)?;
+            self.partial.clear();
+        }
+        Ok(())
+    }
+}
+
+/// The streams of a run to capture once the child is running.
+pub struct Captures {
+    pipes: Vec<Capture>,
+    tails: Vec<Tail>,
+}
+
+impl Captures {
+    pub fn start(self, unit: &str, pid: u32, service: &Service) {
+        let sink = |stream, file| Sink {
+            unit: unit.to_string(),
+            stream,
+            pid,
+            file,
+            timestamps: service.log_timestamps == Some(true),
+            journal: config::journal_enabled(),
+        };
+        for capture in self.pipes {
+            copy(capture.source, sink(capture.stream, capture.file));
+        }
+        if !self.tails.is_empty() {
+            let tails = self.tails.into_iter().map(|tail| {
+                let sink = sink(tail.stream, None);
+                (tail, sink)
+            }).collect();
+            follow(unit, tails);
+        }
+    }
+}
+
+/// Reads the log files of a run adopted from a previous manager instance
+/// into the journal and the recent lines, from their current end on.
+/// Output that went through a pipe of the previous instance is lost.
+pub fn resume(unit: &str, pid: u32, service: &Service, stdout: &Output, stderr: &Output) {
+    if is_captured(service) {
+        return;
+    }
+    let mut tails = Vec::new();
+    for (output, stream) in [(stdout, "stdout"), (stderr, "stderr")] {
+        if let Output::File(path) | Output::Append(path) = output {
+            let offset = fs::metadata(path).map(|meta| meta.len()).unwrap_or_default();
+            tails.push(Tail::new(PathBuf::from(path), stream, offset));
+        }
+    }
+    Captures { pipes: Vec::new(), tails }.start(unit, pid, service);
+}
+
+/// Stops reading the log files of the current run of `unit` once what is
+/// left in them has been read.
+pub fn end(unit: &str) {
+    if let Some(stop) = TAILS.lock().unwrap().remove(unit) {
+        stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Points stdout and stderr of `command` at `stdout` and `stderr`.
+///
+/// Log files the manager does not need to touch are handed to the child so
+/// they survive a manager restart, everything else goes through a pipe.
+pub fn redirect(command: &mut Command, service: &Service, stdout: &Output, stderr: &Output) -> io::Result<Captures> {
+    let mut captures = Captures { pipes: Vec::new(), tails: Vec::new() };
+    let child_stdout = open(stdout, "stdout", service, &mut captures)?;
+    let child_stderr = match stderr {
+        Output::Stdout => child_stdout.try_clone()?,
+        stderr => open(stderr, "stderr", service, &mut captures)?,
+    };
+    command.stdout(child_stdout).stderr(child_stderr);
+    Ok(captures)
+}
+
+fn open(output: &Output, stream: &'static str, service: &Service, captures: &mut Captures) -> io::Result<ChildOutput> {
+    let rotation = Rotation::of(service);
+    let file = match output {
+        Output::File(path) => Some(LogFile::open(PathBuf::from(path), &OutputMode::Truncate, rotation.as_ref())?),
+        Output::Append(path) => Some(LogFile::open(PathBuf::from(path), &OutputMode::Append, rotation.as_ref())?),
+        Output::Journal => None,
+        Output::Null => return Ok(ChildOutput::Null),
+        Output::Inherit => return Ok(ChildOutput::Inherit),
+        Output::Stdout => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} cannot be sent to stdout", stream)));
+        }
+    };
+    match file {
+        Some(file) if !is_captured(service) => {
+            captures.tails.push(Tail::new(file.path.clone(), stream, file.size));
+            Ok(ChildOutput::File(file.into_file()))
+        }
+        file => {
+            let (source, pipe) = io::pipe()?;
+            captures.pipes.push(Capture { source, stream, file });
+            Ok(ChildOutput::Pipe(pipe))
+        }
+    }
+}
+
+/// Where the lines captured from one output stream of a run go.
+struct Sink {
+    unit: String,
+    /// `stdout` or `stderr`.
+    stream: &'static str,
+    pid: u32,
+    file: Option<LogFile>,
+    /// Prefix every line in the file with the time and `[<stream>]`.
+    timestamps: bool,
+    /// Also record every line in the journal.
+    journal: bool,
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let message = String::from_utf8_lossy(line);
+        let message = message.trim_end_matches(['\r', '\n']);
+        remember(Record {
+            ts: Local::now(),
+            unit: self.unit.clone(),
+            stream: self.stream.to_string(),
+            pid: Some(self.pid),
+            message: message.to_string(),
+        });
+        if self.journal {
+            journal::write(&self.unit, self.stream, Some(self.pid), message);
+        }
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        if self.timestamps {
+            let mut prefixed = format!("{} [{}] ", Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), self.stream).into_bytes();
+            prefixed.extend_from_slice(line);
+            if !prefixed.ends_with(b"\n") {
+                prefixed.push(b'\n');
+            }
+            file.write_all(&prefixed)
+        } else {
+            file.write_all(line)
+        }
+    }
+}
+
+fn remember(record: Record) {
+    let mut recent = RECENT.lock().unwrap();
+    let lines = recent.entry(record.unit.clone()).or_default();
+    if lines.len() == RECENT_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(record);
+}
+
+/// The latest lines of output of `name`, oldest first. Output sent to
+/// `null` or `inherit` is never seen by the manager.
+pub fn recent(name: &str) -> Vec<Record> {
+    RECENT.lock().unwrap().get(name).map(|lines| lines.iter().cloned().collect()).unwrap_or_default()
+}
+
+/// Copies what a child writes to `source` into `sink` line by line until the
+/// child and every process that inherited the pipe have closed it.
+fn copy<R>(source: R, mut sink: Sink)
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(source);
+        let mut line = Vec::new();
+        let mut failed = false;
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // Keep draining the pipe after a failed write, or the child blocks.
+                    if let Err(e) = sink.write_line(&line)
+                        && !failed
+                    {
+                        log::error!(unit = sink.unit.as_str(), event = "output_failed", pid = sink.pid; "Failed to write output of service {} to {:?}: {}", sink.unit, sink.file.as_ref().map(|file| &file.path), e);
+                        failed = true;
+                    }
+                }
+                Err(e) => {
+                    log::error!(unit = sink.unit.as_str(), event = "output_failed", pid = sink.pid; "Failed to read output of service {}: {}", sink.unit, e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Reads the log files of the current run of `unit` into their sinks until
+/// `end` is called or the next run starts.
+fn follow(unit: &str, mut tails: Vec<(Tail, Sink)>) {
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = TAILS.lock().unwrap().insert(unit.to_string(), stop.clone()) {
+        previous.store(true, Ordering::Relaxed);
+    }
+
+    thread::spawn(move || {
+        loop {
+            let last = stop.load(Ordering::Relaxed);
+            for (tail, sink) in &mut tails {
+                if let Err(e) = tail.read(sink, last) {
+                    log::debug!(unit = sink.unit.as_str(), event = "output_failed", pid = sink.pid; "Failed to read output of service {} from {:?}: {}", sink.unit, tail.path, e);
+                }
+            }
+            if last {
+                break;
+            }
+            thread::sleep(TAIL_INTERVAL);
+        }
+    });
+}
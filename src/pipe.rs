@@ -1,4 +1,4 @@
-use std::ffi::c_void;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
 use windows::core::PCWSTR;
 use windows::Win32::Foundation as Win32Foundation;
 use windows::Win32::Storage::FileSystem as Win32FileSystem;
@@ -6,19 +6,94 @@ use windows::Win32::System::Pipes as Win32Pipes;
 
 use crate::{Result, Error};
 
+/// Size of the pipe buffers, which bounds the size of a single message.
+pub const BUFFER_SIZE: usize = 64 * 1024;
 
-pub fn listen<F>(pipe_name: PCWSTR, handler: F) -> Result<()>
-where
-    F: Fn(&[u8]) -> Vec<u8>
-{
+/// How long `connect` waits for a free instance of the pipe, in milliseconds.
+const CONNECT_TIMEOUT_MS: u32 = 5000;
+
+/// One end of a pipe connection over which any number of messages can be
+/// exchanged, closed when dropped.
+pub struct Connection {
+    handle: OwnedHandle,
+    /// Whether this is the end created by `accept`.
+    server: bool,
+}
+
+impl Connection {
+    fn raw(&self) -> Win32Foundation::HANDLE {
+        Win32Foundation::HANDLE(self.handle.as_raw_handle())
+    }
+
+    /// Reads the next message, `None` once the other end has closed or
+    /// disconnected the pipe.
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut message = Vec::new();
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        loop {
+            let mut bytes_read: u32 = 0;
+            let result = unsafe {
+                Win32FileSystem::ReadFile(
+                    self.raw(),
+                    Some(&mut buffer),
+                    Some(&mut bytes_read),
+                    None
+                )
+            };
+            message.extend_from_slice(&buffer[..bytes_read as usize]);
+            match result {
+                Ok(()) => return Ok(Some(message)),
+                // The message is larger than the buffer, read the rest.
+                Err(e) if e.code() == Win32Foundation::ERROR_MORE_DATA.to_hresult() => continue,
+                // The server disconnects its end when it is done, the
+                // client just closes it.
+                Err(e) if [
+                    Win32Foundation::ERROR_BROKEN_PIPE,
+                    Win32Foundation::ERROR_PIPE_NOT_CONNECTED,
+                    Win32Foundation::ERROR_NO_DATA,
+                ].iter().any(|code| e.code() == code.to_hresult()) => return Ok(None),
+                Err(e) => return Err(Error::Win32(e)),
+            }
+        }
+    }
+
+    /// Writes one message. Fails once the other end has closed the pipe,
+    /// even for an empty message.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let mut bytes_written: u32 = 0;
+        unsafe {
+            Win32FileSystem::WriteFile(
+                self.raw(),
+                Some(data),
+                Some(&mut bytes_written),
+                None
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.server {
+            unsafe {
+                Win32FileSystem::FlushFileBuffers(self.raw()).ok();
+                Win32Pipes::DisconnectNamedPipe(self.raw()).ok();
+            }
+        }
+    }
+}
+
+/// Creates an instance of the pipe and waits for a client to connect to it.
+pub fn accept(pipe_name: PCWSTR) -> Result<Connection> {
 unsafe {
     let pipe_hdl = Win32Pipes::CreateNamedPipeW(
         pipe_name,
         Win32FileSystem::PIPE_ACCESS_DUPLEX,
         Win32Pipes::PIPE_TYPE_MESSAGE | Win32Pipes::PIPE_READMODE_MESSAGE | Win32Pipes::PIPE_WAIT,
         Win32Pipes::PIPE_UNLIMITED_INSTANCES,
-        1024,
-        1024,
+        BUFFER_SIZE as u32,
+        BUFFER_SIZE as u32,
         0,
         None,
     );
@@ -26,62 +101,62 @@ unsafe {
     if pipe_hdl.is_invalid() {
         return Err(Error::Win32(windows::core::Error::from_win32()));
     }
+    let connection = Connection { handle: OwnedHandle::from_raw_handle(pipe_hdl.0), server: true };
 
-    // Start listening for incoming connections.
-    Win32Pipes::ConnectNamedPipe(pipe_hdl, None)?;
-
-    let mut read_buffer = [0u8; 1024];
-    let mut bytes_read: u32 = 0;
-    
-    Win32FileSystem::ReadFile(
-        pipe_hdl, 
-        Some(&mut read_buffer), 
-        Some(&mut bytes_read), 
-        None
-    )?;
-
-    let mut bytes_written: u32 = 0;
-
-    Win32FileSystem::WriteFile(
-        pipe_hdl,
-        Some(&handler(&read_buffer[..bytes_read as usize])),
-        Some(&mut bytes_written), 
-        None
-    )?;
-    
-    Win32FileSystem::FlushFileBuffers(pipe_hdl)?;
-    
-    Win32Pipes::DisconnectNamedPipe(pipe_hdl)?;
-    Win32Foundation::CloseHandle(pipe_hdl)?;
-    Ok(())
+    // Start listening for incoming connections. A client may already have
+    // connected between creating the instance and this call.
+    match Win32Pipes::ConnectNamedPipe(pipe_hdl, None) {
+        Ok(()) => Ok(connection),
+        Err(e) if e.code() == Win32Foundation::ERROR_PIPE_CONNECTED.to_hresult() => Ok(connection),
+        Err(e) => Err(Error::Win32(e)),
+    }
 }
 }
 
-
-pub fn send(pipe_name: PCWSTR, data: &[u8]) -> Result<Vec<u8>> {
-    unsafe {
-        
-        let mut read_buffer = [0u8; 1024];
-        let mut bytes_read: u32 = 0;
-
-        let result = Win32Pipes::CallNamedPipeW(
+/// Connects to a pipe created by `accept`, waiting while all of its
+/// instances are busy.
+pub fn connect(pipe_name: PCWSTR) -> Result<Connection> {
+unsafe {
+    let pipe_hdl = loop {
+        match Win32FileSystem::CreateFileW(
             pipe_name,
-            Some(data.as_ptr() as *const c_void),
-            data.len() as u32,
-            Some(read_buffer.as_mut_ptr() as *mut c_void),
-            1024,
-            &mut bytes_read,
-            Win32Pipes::NMPWAIT_USE_DEFAULT_WAIT,
-        );
-
-        if result.as_bool() {
-            if bytes_read > 0 {
-                Ok(read_buffer[..bytes_read as usize].to_vec())
-            } else {
-                Err(Error::Win32(windows::core::Error::from_win32()))
+            (Win32Foundation::GENERIC_READ | Win32Foundation::GENERIC_WRITE).0,
+            Win32FileSystem::FILE_SHARE_NONE,
+            None,
+            Win32FileSystem::OPEN_EXISTING,
+            Win32FileSystem::FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        ) {
+            Ok(pipe_hdl) => break pipe_hdl,
+            Err(e) if e.code() == Win32Foundation::ERROR_PIPE_BUSY.to_hresult() => {
+                if !Win32Pipes::WaitNamedPipeW(pipe_name, CONNECT_TIMEOUT_MS).as_bool() {
+                    return Err(Error::Win32(windows::core::Error::from_win32()));
+                }
             }
-        } else {
-            Err(Error::Win32(windows::core::Error::from_win32()))
+            Err(e) => return Err(Error::Win32(e)),
         }
-    }
+    };
+    let connection = Connection { handle: OwnedHandle::from_raw_handle(pipe_hdl.0), server: false };
+
+    Win32Pipes::SetNamedPipeHandleState(pipe_hdl, Some(&Win32Pipes::PIPE_READMODE_MESSAGE), None, None)?;
+    Ok(connection)
+}
+}
+
+/// Serves a single request with a single reply.
+pub fn listen<F>(pipe_name: PCWSTR, handler: F) -> Result<()>
+where
+    F: Fn(&[u8]) -> Vec<u8>
+{
+    let mut connection = accept(pipe_name)?;
+    let request = connection.read()?.unwrap_or_default();
+    connection.write(&handler(&request))
+}
+
+
+/// Sends a request and waits for its reply, however long the server takes.
+pub fn send(pipe_name: PCWSTR, data: &[u8]) -> Result<Vec<u8>> {
+    let mut connection = connect(pipe_name)?;
+    connection.write(data)?;
+    Ok(connection.read()?.unwrap_or_default())
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+//! Health probes, on the standard library alone so they build and can be
+//! tested on any platform: `rustc --edition 2024 --test src/probe.rs`.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::thread;
+
+/// A TCP connection to `address` must succeed.
+pub fn tcp(address: &str, timeout: Duration) -> Result<(), String> {
+    connect(address, timeout).map(|_| ())
+}
+
+/// A GET of an `http://` URL must answer with `expected_status`.
+pub fn http(url: &str, expected_status: u16, timeout: Duration) -> Result<(), String> {
+    let status = http_get(url, timeout)?;
+    if status == expected_status {
+        Ok(())
+    } else {
+        Err(format!("HTTP status {}, expected {}", status, expected_status))
+    }
+}
+
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let addr = address.to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", address, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", address))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| format!("connect to {}: {}", address, e))?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+/// Sends a plain HTTP/1.1 GET and returns the response status code.
+fn http_get(url: &str, timeout: Duration) -> Result<u16, String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("only http:// URLs are supported: {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+
+    let mut stream = connect(&address, timeout)?;
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).map_err(|e| format!("send request: {}", e))?;
+
+    // Only the status line is needed, e.g. `HTTP/1.1 200 OK`.
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buffer[..n]),
+            Err(e) => return Err(format!("read response: {}", e)),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    response.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| format!("invalid response `{}`", response.lines().next().unwrap_or_default()))
+}
+
+/// `path` run with `args` must exit with code 0 within `timeout`.
+pub fn command(path: &str, args: &[String], timeout: Duration) -> Result<(), String> {
+    let mut child = Command::new(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("run {}: {}", path, e))?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("{} exited with {}", path, status)),
+            Ok(None) if Instant::now() >= deadline => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("{} timed out", path));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(format!("wait for {}: {}", path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Answers one HTTP request on a local port with `status_line` and
+    /// returns the `http://` URL to reach it.
+    fn serve_once(status_line: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/healthz", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+            let response = format!("{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status_line);
+            stream.write_all(response.as_bytes()).ok();
+        });
+        url
+    }
+
+    #[test]
+    fn tcp_probe_connects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert_eq!(tcp(&address, TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn tcp_probe_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let error = connect(&address, TIMEOUT).unwrap_err();
+        assert!(error.starts_with(&format!("connect to {}", address)), "{}", error);
+    }
+
+    #[test]
+    fn http_probe_ok() {
+        let url = serve_once("HTTP/1.1 200 OK");
+        assert_eq!(http_get(&url, TIMEOUT), Ok(200));
+        let url = serve_once("HTTP/1.1 200 OK");
+        assert_eq!(http(&url, 200, TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn http_probe_status_mismatch() {
+        let url = serve_once("HTTP/1.1 503 Service Unavailable");
+        assert_eq!(http(&url, 204, TIMEOUT), Err("HTTP status 503, expected 204".to_string()));
+    }
+
+    #[test]
+    fn http_probe_times_out() {
+        // Connections are queued by the listener but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let started = Instant::now();
+        let error = http_get(&url, Duration::from_millis(300)).unwrap_err();
+        assert!(error.starts_with("read response"), "{}", error);
+        assert!(started.elapsed() < TIMEOUT);
+        drop(listener);
+    }
+}
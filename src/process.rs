@@ -2,18 +2,376 @@ use once_cell::sync::Lazy;
 use core::fmt;
 use std::sync::Mutex;
 use std::collections::HashMap;
-use std::process::{Command, Child};
-use std::{fs, io};
+use std::process::{Command, Child, Stdio};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::os::windows::process::CommandExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
+use std::io::Write;
 use std::result::Result;
-use crate::config::Service;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Local};
+use windows::core::PWSTR;
+use windows::Win32::Foundation as Win32Foundation;
+use windows::Win32::System::Console as Win32Console;
+use windows::Win32::System::Threading as Win32Threading;
+use windows::Win32::UI::WindowsAndMessaging as Win32WindowsAndMessaging;
+use crate::config::{self, Input, KillMode, Output, Service};
+use crate::job::{self, Job};
+use crate::history::{self, Cause};
+use crate::notify;
+use crate::output;
 
-static PROCESS_MAP: Lazy<Mutex<HashMap<String, Child>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static PROCESS_MAP: Lazy<Mutex<HashMap<String, Process>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Set by `close` when the manager shuts down. Held by `spawn` until the
+/// new process is in `PROCESS_MAP`, so the shutdown sees every process.
+static CLOSED: Mutex<bool> = Mutex::new(false);
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of stderr lines returned when a process exits during start.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// A managed child, either spawned by this manager instance or adopted from
+/// the state file left by a previous one.
+pub struct Process {
+    pub pid: u32,
+    /// Creation time of the process, used to tell it apart from a later
+    /// process that reuses the same PID.
+    pub start_time: u64,
+    pub path: String,
+    pub kill_mode: KillMode,
+    /// Exit codes besides 0 that count as success.
+    pub success_exit_status: Vec<u32>,
+    /// The console process group the service was started in, whose ID is
+    /// that of its first process. `None` if it was started without one.
+    group: Option<u32>,
+    handle: Handle,
+    /// The job holding the process tree, if it could be created.
+    job: Option<Job>,
+}
+
+enum Handle {
+    Child(Child),
+    Adopted(OwnedHandle),
+}
+
+/// What is persisted to the state file for each managed process.
+#[derive(Serialize, Deserialize, Debug)]
+struct State {
+    pid: u32,
+    start_time: u64,
+    path: String,
+    #[serde(default)]
+    kill_mode: KillMode,
+    #[serde(default)]
+    success_exit_status: Vec<u32>,
+    #[serde(default)]
+    group: Option<u32>,
+}
+
+impl Process {
+    fn raw_handle(&self) -> Win32Foundation::HANDLE {
+        match &self.handle {
+            Handle::Child(child) => Win32Foundation::HANDLE(child.as_raw_handle()),
+            Handle::Adopted(handle) => Win32Foundation::HANDLE(handle.as_raw_handle()),
+        }
+    }
+
+    pub fn is_success(&self, code: u32) -> bool {
+        code == 0 || self.success_exit_status.contains(&code)
+    }
+
+    /// Returns the exit code if the process has exited.
+    pub fn try_wait(&mut self) -> io::Result<Option<u32>> {
+        match &mut self.handle {
+            Handle::Child(child) => Ok(child.try_wait()?.map(|status| status.code().unwrap_or(i32::MIN) as u32)),
+            Handle::Adopted(_) => unsafe {
+                if Win32Threading::WaitForSingleObject(self.raw_handle(), 0) != Win32Foundation::WAIT_OBJECT_0 {
+                    return Ok(None);
+                }
+                let mut code = 0u32;
+                Win32Threading::GetExitCodeProcess(self.raw_handle(), &mut code)?;
+                Ok(Some(code))
+            },
+        }
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        match &mut self.handle {
+            Handle::Child(child) => child.kill(),
+            Handle::Adopted(_) => unsafe {
+                Win32Threading::TerminateProcess(self.raw_handle(), 1)?;
+                Ok(())
+            },
+        }
+    }
+
+    /// Waits up to `timeout` for the process to exit, returning whether it did.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let millis = timeout.as_millis().min(Win32Threading::INFINITE as u128 - 1) as u32;
+        unsafe {
+            Win32Threading::WaitForSingleObject(self.raw_handle(), millis) == Win32Foundation::WAIT_OBJECT_0
+        }
+    }
+
+    /// Returns the PIDs of the process tree, or just the main process if it
+    /// is not tracked by a job.
+    pub fn members(&self) -> Vec<u32> {
+        match self.job.as_ref().map(|job| job.members()) {
+            Some(Ok(members)) => members,
+            _ => vec![self.pid],
+        }
+    }
+
+    /// Stops the process tree according to its kill mode, giving it
+    /// `timeout` to close its windows or handle CTRL_BREAK and exit before
+    /// it is killed.
+    /// Returns the exit code of the main process.
+    fn terminate(&mut self, timeout: Duration) -> io::Result<u32> {
+        let deadline = Instant::now() + timeout;
+        let graceful = match self.kill_mode {
+            KillMode::ControlGroup => self.members(),
+            KillMode::Process | KillMode::Mixed => vec![self.pid],
+        };
+
+        if self.try_wait()?.is_none() {
+            let mut asked = false;
+            if !timeout.is_zero() {
+                for pid in graceful {
+                    asked |= request_close(pid);
+                }
+                if let Some(group) = self.group {
+                    asked |= request_break(group);
+                }
+            }
+            let exited = asked && self.wait_timeout(timeout) && match (&self.kill_mode, &self.job) {
+                (KillMode::ControlGroup, Some(job)) => job.wait_empty(deadline),
+                _ => true,
+            };
+            if !exited {
+                self.kill()?;
+            }
+        }
+
+        if self.kill_mode != KillMode::Process && let Some(job) = &self.job {
+            job.terminate(1)?;
+        }
+        self.wait()
+    }
+
+    /// Waits for the process to exit and returns its exit code.
+    pub fn wait(&mut self) -> io::Result<u32> {
+        match &mut self.handle {
+            Handle::Child(child) => Ok(child.wait()?.code().unwrap_or(i32::MIN) as u32),
+            Handle::Adopted(_) => unsafe {
+                Win32Threading::WaitForSingleObject(self.raw_handle(), Win32Threading::INFINITE);
+                let mut code = 0u32;
+                Win32Threading::GetExitCodeProcess(self.raw_handle(), &mut code)?;
+                Ok(code)
+            },
+        }
+    }
+}
+
+/// Converts a process creation time to local time.
+pub fn filetime_to_local(filetime: u64) -> DateTime<Local> {
+    // FILETIME counts 100ns intervals since 1601-01-01.
+    const UNIX_EPOCH_OFFSET: u64 = 116_444_736_000_000_000;
+    let nanos = filetime.saturating_sub(UNIX_EPOCH_OFFSET) as i64 * 100;
+    DateTime::from_timestamp_nanos(nanos).with_timezone(&Local)
+}
+
+/// Returns the creation time and full image path of a process.
+fn identity(handle: Win32Foundation::HANDLE) -> io::Result<(u64, String)> {
+    unsafe {
+        let mut creation = Win32Foundation::FILETIME::default();
+        let mut exit = Win32Foundation::FILETIME::default();
+        let mut kernel = Win32Foundation::FILETIME::default();
+        let mut user = Win32Foundation::FILETIME::default();
+        Win32Threading::GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user)?;
+
+        let mut buffer = [0u16; 1024];
+        let mut size = buffer.len() as u32;
+        Win32Threading::QueryFullProcessImageNameW(
+            handle,
+            Win32Threading::PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )?;
+
+        Ok((
+            ((creation.dwHighDateTime as u64) << 32) | creation.dwLowDateTime as u64,
+            String::from_utf16_lossy(&buffer[..size as usize]),
+        ))
+    }
+}
+
+/// Asks a process to exit by posting `WM_CLOSE` to its top-level windows.
+/// Returns whether any window was found.
+fn request_close(pid: u32) -> bool {
+    unsafe extern "system" fn close_window(
+        hwnd: Win32Foundation::HWND,
+        lparam: Win32Foundation::LPARAM,
+    ) -> windows::core::BOOL {
+        unsafe {
+            let target = &mut *(lparam.0 as *mut (u32, bool));
+            let mut pid = 0u32;
+            Win32WindowsAndMessaging::GetWindowThreadProcessId(hwnd, Some(&mut pid));
+            if pid == target.0 && Win32WindowsAndMessaging::PostMessageW(
+                Some(hwnd),
+                Win32WindowsAndMessaging::WM_CLOSE,
+                Win32Foundation::WPARAM(0),
+                Win32Foundation::LPARAM(0),
+            ).is_ok() {
+                target.1 = true;
+            }
+        }
+        Win32Foundation::TRUE
+    }
+
+    let mut target = (pid, false);
+    unsafe {
+        Win32WindowsAndMessaging::EnumWindows(
+            Some(close_window),
+            Win32Foundation::LPARAM(&mut target as *mut (u32, bool) as isize),
+        ).ok();
+    }
+    target.1
+}
+
+/// Asks the console processes of `group` to exit by sending them
+/// CTRL_BREAK_EVENT. A manager without a console, as when it runs as a
+/// Windows service, attaches to the console of the group for that.
+/// Returns whether the event was sent.
+fn request_break(group: u32) -> bool {
+    // The console a process is attached to is shared by all its threads.
+    static CONSOLE: Mutex<()> = Mutex::new(());
+    let _console = CONSOLE.lock().unwrap();
+    unsafe {
+        if Win32Console::GenerateConsoleCtrlEvent(Win32Console::CTRL_BREAK_EVENT, group).is_ok() {
+            return true;
+        }
+        // Fails if the manager has a console of its own, or the group has none.
+        if Win32Console::AttachConsole(group).is_err() {
+            return false;
+        }
+        let sent = Win32Console::GenerateConsoleCtrlEvent(Win32Console::CTRL_BREAK_EVENT, group).is_ok();
+        Win32Console::FreeConsole().ok();
+        sent
+    }
+}
+
+fn state_path() -> io::Result<PathBuf> {
+    Ok(std::env::current_exe()?.with_file_name("state.json"))
+}
+
+/// Writes the PID, start time and path of every managed process to the state file.
+fn persist(process_map: &HashMap<String, Process>) {
+    let state: HashMap<&String, State> = process_map.iter().map(|(name, process)| {
+        (name, State {
+            pid: process.pid,
+            start_time: process.start_time,
+            path: process.path.clone(),
+            kill_mode: process.kill_mode.clone(),
+            success_exit_status: process.success_exit_status.clone(),
+            group: process.group,
+        })
+    }).collect();
+
+    // Written next to the state file and renamed over it, so a crash never
+    // leaves a truncated state file behind.
+    let result = state_path().and_then(|path| {
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(&state).map_err(io::Error::other)?)?;
+        fs::rename(temp, path)
+    });
+    if let Err(e) = result {
+        log::error!(event = "state_failed"; "Failed to persist process state: {}", e);
+    }
+}
+
+/// Re-adopts the processes recorded in the state file that are still running
+/// with the same start time and image path.
+pub fn adopt() {
+    let content = match state_path().and_then(fs::read_to_string) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            log::error!(event = "state_failed"; "Failed to read process state: {}", e);
+            return;
+        }
+    };
+    let state: HashMap<String, State> = match serde_json::from_str(&content) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!(event = "state_failed"; "Failed to parse process state: {}", e);
+            return;
+        }
+    };
+
+    let mut process_map = PROCESS_MAP.lock().unwrap();
+    for (name, state) in state {
+        let handle = unsafe {
+            match Win32Threading::OpenProcess(
+                Win32Threading::PROCESS_QUERY_LIMITED_INFORMATION
+                    | Win32Threading::PROCESS_TERMINATE
+                    | Win32Threading::PROCESS_SYNCHRONIZE,
+                false,
+                state.pid,
+            ) {
+                Ok(handle) => OwnedHandle::from_raw_handle(handle.0),
+                Err(_) => continue,
+            }
+        };
+
+        let mut process = Process {
+            pid: state.pid,
+            start_time: state.start_time,
+            path: state.path,
+            kill_mode: state.kill_mode,
+            success_exit_status: state.success_exit_status,
+            group: state.group,
+            handle: Handle::Adopted(handle),
+            job: None,
+        };
+
+        let same = identity(process.raw_handle())
+            .is_ok_and(|(start_time, path)| start_time == process.start_time && path == process.path);
+        if same && matches!(process.try_wait(), Ok(None)) {
+            process.job = track_adopted(&name, process.pid);
+            log::info!(unit = name.as_str(), event = "adopted", pid = process.pid; "Adopted running service {} (pid {}).", name, process.pid);
+            history::started(&name, process.pid, filetime_to_local(process.start_time), Cause::Adopted);
+            if let Some(service) = config::get(&name, |config| config.service.clone())
+                && let Ok((stdout, stderr)) = outputs(&name, &service)
+            {
+                output::resume(&name, process.pid, &service, &stdout, &stderr);
+            }
+            process_map.insert(name, process);
+        } else {
+            log::info!(unit = name.as_str(), event = "gone", pid = state.pid; "Service {} (pid {}) from the state file is no longer running.", name, state.pid);
+        }
+    }
+
+    persist(&process_map);
+}
 
 #[derive(Debug)]
 pub enum Error {
     ProcessNotFound,
     ProcessAlreadyRunning,
     ProcessExited(u32),
+    /// Exited with a failure code before it was ready, with the end of its stderr log.
+    ExitedDuringStart(u32, String),
+    /// Did not report `READY=1` within its start timeout.
+    NotReady,
+    /// A `MAINPID=` outside the unit's process tree.
+    NotAMember(u32),
+    AssertionFailed(String),
+    /// The manager is shutting down and starts nothing anymore.
+    ShuttingDown,
     Io(io::Error),
 }
 
@@ -30,11 +388,143 @@ impl fmt::Display for Error {
             Error::Io(e) => write!(f, "IoError({})", e),
             Error::ProcessNotFound => write!(f, "ProcessNotFound"),
             Error::ProcessExited(code) => write!(f, "ProcessExited({})", code),
+            Error::ExitedDuringStart(code, _) => write!(f, "ExitedDuringStart({})", code),
+            Error::NotReady => write!(f, "NotReady"),
+            Error::NotAMember(pid) => write!(f, "NotAMember({})", pid),
             Error::ProcessAlreadyRunning => write!(f, "ProcessAlreadyRunning"),
+            Error::AssertionFailed(assertion) => write!(f, "AssertionFailed({})", assertion),
+            Error::ShuttingDown => write!(f, "ShuttingDown"),
+        }
+    }
+}
+/// Returns the configured log file of a stream, or `log/<name>-<stream>.log`
+/// next to the executable, creating the `log` directory if needed.
+pub fn log_file(name: &str, stream: &str) -> io::Result<String> {
+    let log_path = std::env::current_exe()?
+        .parent()
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "Could not find parent directory of current_exe")
+        })?
+        .join("log");
+
+    fs::create_dir_all(&log_path)?;
+    Ok(log_path.join(format!("{}-{}.log", name, stream)).to_string_lossy().into_owned())
+}
+
+/// Where the stdout and stderr of `name` go, with the default log files
+/// filled in.
+fn outputs(name: &str, service: &Service) -> io::Result<(Output, Output)> {
+    let stdout = match &service.stdout {
+        Some(stdout) => stdout.clone(),
+        None => Output::File(log_file(name, "stdout")?),
+    };
+    let stderr = match (&service.stderr, &stdout) {
+        (Some(stderr), _) => stderr.clone(),
+        (None, Output::File(_)) => Output::File(log_file(name, "stderr")?),
+        (None, Output::Append(_)) => Output::Append(log_file(name, "stderr")?),
+        (None, stdout) => stdout.clone(),
+    };
+    Ok((stdout, stderr))
+}
+
+/// Points the stdin of `command` at what `service` configures. Data is
+/// written from a thread of its own, as it may not fit into the pipe.
+fn redirect_stdin(name: &str, command: &mut Command, service: &Service) -> io::Result<()> {
+    let input = service.stdin.clone().unwrap_or(match service.stdin_data {
+        Some(_) => Input::Data,
+        None => Input::Null,
+    });
+    match input {
+        Input::Null => {
+            command.stdin(Stdio::null());
+        }
+        Input::File(path) => {
+            command.stdin(fs::File::open(path)?);
+        }
+        Input::Data => {
+            let (source, mut pipe) = io::pipe()?;
+            command.stdin(source);
+            let (name, data) = (name.to_string(), service.stdin_data.clone().unwrap_or_default());
+            thread::spawn(move || {
+                // Fails if the process exits without reading all of it.
+                if let Err(e) = pipe.write_all(data.as_bytes()) {
+                    log::debug!(unit = name.as_str(), event = "stdin_failed"; "Failed to write stdin of service {}: {}", name, e);
+                }
+            });
         }
     }
+    Ok(())
+}
+
+/// Returns the last lines written to the stderr of `name`, from its log
+/// file or, if it has none, from the captured output.
+fn stderr_tail(name: &str, service: &Service) -> String {
+    let path = match outputs(name, service) {
+        Ok((Output::File(path) | Output::Append(path), Output::Stdout)) => path,
+        Ok((_, Output::File(path) | Output::Append(path))) => path,
+        _ => {
+            let lines: Vec<String> = output::recent(name).into_iter()
+                .filter(|record| record.stream == "stderr")
+                .map(|record| record.message)
+                .collect();
+            return lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+        }
+    };
+    let content = fs::read(path)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Reads the identity of a child started suspended, puts it in a job and
+/// lets it run.
+fn track(name: &str, child: &Child) -> io::Result<(u64, String, Option<Job>)> {
+    let handle = Win32Foundation::HANDLE(child.as_raw_handle());
+    let (start_time, path) = identity(handle)?;
+
+    let job = Job::create().and_then(|job| {
+        job.assign(handle)?;
+        Ok(job)
+    });
+    if let Err(e) = &job {
+        log::warn!(unit = name, event = "untracked", pid = child.id(); "Service {} is not tracked by a job, only its main process can be stopped: {}", name, e);
+    }
+
+    job::resume_threads(child.id())?;
+    Ok((start_time, path, job.ok()))
 }
-pub fn spawn(name: &str, service: &Service) -> Result<(), Error> {
+
+/// Puts the process tree of an adopted process into a new job, as the job
+/// of the previous manager instance went away with it.
+fn track_adopted(name: &str, pid: u32) -> Option<Job> {
+    let job = Job::create().and_then(|job| {
+        let tree = job::process_tree(pid)?;
+        job.assign_pid(pid)?;
+        for &member in &tree[1..] {
+            if let Err(e) = job.assign_pid(member) {
+                log::debug!(unit = name, event = "untracked", pid = member; "Process {} of service {} is not tracked by its job: {}", member, name, e);
+            }
+        }
+        Ok(job)
+    });
+    if let Err(e) = &job {
+        log::warn!(unit = name, event = "untracked", pid = pid; "Service {} is not tracked by a job, only its main process can be stopped: {}", name, e);
+    }
+    job.ok()
+}
+
+/// Makes every later `spawn` fail with `ShuttingDown`, once the spawns in
+/// progress are done.
+pub fn close() {
+    *CLOSED.lock().unwrap() = true;
+}
+
+pub fn spawn(name: &str, service: &Service, cause: Cause) -> Result<(), Error> {
+    let closed = CLOSED.lock().unwrap();
+    if *closed {
+        return Err(Error::ShuttingDown);
+    }
     if check(name).is_ok() {
         return Err(Error::ProcessAlreadyRunning);
     }
@@ -46,31 +536,52 @@ pub fn spawn(name: &str, service: &Service) -> Result<(), Error> {
     if let Some(env) = &service.env {
         command.envs(env);
     }
+    if let Some(working_directory) = &service.working_directory {
+        command.current_dir(working_directory);
+    }
+    if config::uses_notify(service) {
+        command.env(notify::ENV_NAME, notify::open(name)?);
+    }
+    if let Some(watchdog_sec) = service.watchdog_sec {
+        command.env("WATCHDOG_USEC", (watchdog_sec * 1_000_000).to_string());
+    }
 
-    let log_path = std::env::current_exe()?
-        .parent()
-        .ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, "Could not find parent directory of current_exe")
-        })?
-        .join("log");
-    
-    fs::create_dir_all(&log_path)?;
+    redirect_stdin(name, &mut command, service)?;
+    let (stdout, stderr) = outputs(name, service)?;
+    let captures = output::redirect(&mut command, service, &stdout, &stderr)?;
 
-    command.stdout(match &service.stdout_path {
-        Some(stdout_path) => fs::File::create(stdout_path)?,
-        None => fs::File::create(
-            log_path.join(format!("{}-stdout.log", name))
-        )?
-    });
+    // Start suspended so the process is in its job before it can start
+    // children, and in a process group of its own so CTRL_BREAK can be sent
+    // to it alone.
+    command.creation_flags(Win32Threading::CREATE_SUSPENDED.0 | Win32Threading::CREATE_NEW_PROCESS_GROUP.0);
+    let mut child = command.spawn()?;
+    let (start_time, path, job) = match track(name, &child) {
+        Ok(tracked) => tracked,
+        Err(e) => {
+            child.kill().ok();
+            return Err(Error::Io(e));
+        }
+    };
 
-    command.stderr(match &service.stderr_path {
-        Some(stderr_path) => fs::File::create(stderr_path)?,
-        None => fs::File::create(
-            log_path.join(format!("{}-stderr.log", name))
-        )?
-    });
+    // The pipe ends of the child are closed with `command`, so the capture
+    // ends when the child and its children have exited.
+    drop(command);
+    captures.start(name, child.id(), service);
 
-    PROCESS_MAP.lock().unwrap().insert(name.to_string(), command.spawn()?);
+    history::started(name, child.id(), filetime_to_local(start_time), cause);
+
+    let mut process_map = PROCESS_MAP.lock().unwrap();
+    process_map.insert(name.to_string(), Process {
+        pid: child.id(),
+        start_time,
+        path,
+        kill_mode: service.kill_mode.clone().unwrap_or_default(),
+        success_exit_status: service.success_exit_status.clone().unwrap_or_default(),
+        group: Some(child.id()),
+        handle: Handle::Child(child),
+        job,
+    });
+    persist(&process_map);
     
     Ok(())
 }
@@ -78,7 +589,7 @@ pub fn spawn(name: &str, service: &Service) -> Result<(), Error> {
 
 pub fn get<F, R>(name: &str, f: F) -> Result<R, Error>
 where
-    F: FnOnce(&Child) -> R,
+    F: FnOnce(&Process) -> R,
 {
     let process_map = PROCESS_MAP.lock().unwrap();
 
@@ -90,7 +601,7 @@ where
 
 pub fn get_mut<F, R>(name: &str, f: F) -> Result<R, Error>
 where
-    F: FnOnce(&mut Child) -> R,
+    F: FnOnce(&mut Process) -> R,
 {
     let mut process_map = PROCESS_MAP.lock().unwrap();
 
@@ -101,10 +612,12 @@ where
 }
 
 pub fn check(name: &str) -> Result<(), Error> {
-    get_mut(name, |child| {
-        match child.try_wait() {
-            Ok(Some(status)) => {
-                Err(Error::ProcessExited(status.code().unwrap_or(i32::MIN) as u32))
+    get_mut(name, |process| {
+        match process.try_wait() {
+            Ok(Some(code)) => {
+                history::ended(name, code, process.is_success(code), None);
+                output::end(name);
+                Err(Error::ProcessExited(code))
             }
             Ok(None) => {
                 Ok(())
@@ -117,17 +630,92 @@ pub fn check(name: &str) -> Result<(), Error> {
     })?
 }
 
+/// Waits until `name` has reported `READY=1`, or for units without
+/// `notify`, until it has stayed alive for `timeout`. Fails with the exit
+/// code and the end of the stderr log if it exits with a failure code
+/// before that.
+pub fn wait_ready(name: &str, service: &Service, timeout: Duration) -> Result<(), Error> {
+    let notify = service.notify == Some(true);
+    let deadline = Instant::now() + timeout;
+    loop {
+        match check(name) {
+            Ok(()) if notify && notify::get(name).ready => return Ok(()),
+            Ok(()) if Instant::now() >= deadline && notify => return Err(Error::NotReady),
+            Ok(()) if Instant::now() >= deadline => return Ok(()),
+            Ok(()) => thread::sleep(READY_POLL_INTERVAL),
+            Err(Error::ProcessExited(code)) if is_success(name, code) => return Ok(()),
+            Err(Error::ProcessExited(code)) => {
+                return Err(Error::ExitedDuringStart(code, stderr_tail(name, service)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
 
+/// Whether `code` counts as a successful exit of `name`, that is 0 or one of
+/// its `success_exit_status`.
+pub fn is_success(name: &str, code: u32) -> bool {
+    get(name, |process| process.is_success(code)).unwrap_or(code == 0)
+}
 
-pub fn stop(name: &str) -> Result<(), Error> {
+/// Makes `pid`, which must belong to the process tree of `name`, its main
+/// process.
+pub fn set_main_pid(name: &str, pid: u32) -> Result<(), Error> {
     let mut process_map = PROCESS_MAP.lock().unwrap();
+    let process = process_map.get_mut(name).ok_or(Error::ProcessNotFound)?;
+    if pid == process.pid {
+        return Ok(());
+    }
+    if !process.members().contains(&pid) {
+        return Err(Error::NotAMember(pid));
+    }
 
-    match process_map.remove(name) {
-        Some(mut child) => {
-            child.kill()?;
-            child.wait()?;
-            Ok(())
-        }
-        None => Err(Error::ProcessNotFound),
+    let handle = unsafe {
+        let handle = Win32Threading::OpenProcess(
+            Win32Threading::PROCESS_QUERY_LIMITED_INFORMATION
+                | Win32Threading::PROCESS_TERMINATE
+                | Win32Threading::PROCESS_SYNCHRONIZE,
+            false,
+            pid,
+        ).map_err(io::Error::from)?;
+        OwnedHandle::from_raw_handle(handle.0)
+    };
+    let (start_time, path) = identity(Win32Foundation::HANDLE(handle.as_raw_handle()))?;
+
+    process.pid = pid;
+    process.start_time = start_time;
+    process.path = path;
+    process.handle = Handle::Adopted(handle);
+    persist(&process_map);
+    Ok(())
+}
+
+/// Returns the names of all managed processes, running or exited.
+pub fn names() -> Vec<String> {
+    PROCESS_MAP.lock().unwrap().keys().cloned().collect()
+}
+
+/// Stops a process, giving it `timeout` to close its windows and exit
+/// before it is killed.
+pub fn stop(name: &str, timeout: Duration, cause: Cause) -> Result<(), Error> {
+    let mut process = {
+        let mut process_map = PROCESS_MAP.lock().unwrap();
+        let process = process_map.remove(name).ok_or(Error::ProcessNotFound)?;
+        persist(&process_map);
+        process
+    };
+
+    let exited = process.try_wait()?;
+    let code = process.terminate(timeout)?;
+    output::end(name);
+    match exited {
+        Some(code) => history::ended(name, code, process.is_success(code), None),
+        None => history::ended(name, code, process.is_success(code), Some(cause)),
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// Returns the PIDs of every process belonging to `name`.
+pub fn members(name: &str) -> Result<Vec<u32>, Error> {
+    get(name, |process| process.members())
+}
@@ -0,0 +1,183 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+
+/// When each unit was last sampled by `cpu_percent` and its CPU time then.
+static PREVIOUS: Lazy<Mutex<HashMap<String, (Instant, Duration)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Resource usage of a process tree, summed up over its processes.
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    /// User and kernel time.
+    pub cpu_time: Duration,
+    /// Working set, in bytes.
+    pub memory: u64,
+    /// Open handles.
+    pub handles: u64,
+    pub threads: u64,
+    /// Processes that could be sampled.
+    pub processes: usize,
+    /// When the oldest of them started.
+    pub started: Option<DateTime<Local>>,
+}
+
+impl Usage {
+    pub fn uptime(&self) -> Option<Duration> {
+        self.started.map(|started| (Local::now() - started).to_std().unwrap_or_default())
+    }
+}
+
+/// One process, as read by `platform::sample`.
+struct Sample {
+    cpu_time: Duration,
+    memory: u64,
+    handles: u64,
+    threads: u64,
+    started: DateTime<Local>,
+}
+
+/// Samples `pids` and sums them up. Processes that exited meanwhile or
+/// cannot be inspected are left out.
+pub fn sample(pids: &[u32]) -> Usage {
+    let mut usage = Usage::default();
+    for sample in platform::sample(pids) {
+        usage.cpu_time += sample.cpu_time;
+        usage.memory += sample.memory;
+        usage.handles += sample.handles;
+        usage.threads += sample.threads;
+        usage.processes += 1;
+        usage.started = Some(usage.started.map_or(sample.started, |started| started.min(sample.started)));
+    }
+    usage
+}
+
+/// CPU usage of `name` since it was last passed here, in percent of one
+/// core like `top`. The first time, and after its CPU time went down because
+/// processes exited, it is averaged over the uptime instead.
+pub fn cpu_percent(name: &str, usage: &Usage) -> Option<f64> {
+    let mut previous = PREVIOUS.lock().unwrap();
+    if usage.processes == 0 {
+        previous.remove(name);
+        return None;
+    }
+    let now = Instant::now();
+    let (cpu_time, elapsed) = match previous.insert(name.to_string(), (now, usage.cpu_time)) {
+        Some((time, cpu_time)) if usage.cpu_time >= cpu_time && now > time => (usage.cpu_time - cpu_time, now - time),
+        _ => (usage.cpu_time, usage.uptime()?),
+    };
+    if elapsed.is_zero() {
+        return None;
+    }
+    Some(cpu_time.as_secs_f64() / elapsed.as_secs_f64() * 100.0)
+}
+
+/// Formats a byte count like `12.3M`.
+pub fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+/// Formats a duration like `1d 2h`, `3h 4min`, `5min 6s` or `7.890s`.
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        86400.. => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+        3600.. => format!("{}h {}min", secs / 3600, secs % 3600 / 60),
+        60.. => format!("{}min {}s", secs / 60, secs % 60),
+        _ => format!("{:.3}s", duration.as_secs_f64()),
+    }
+}
+
+mod platform {
+    use std::collections::HashMap;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::time::Duration;
+    use std::{io, mem};
+    use windows::Win32::Foundation as Win32Foundation;
+    use windows::Win32::System::Diagnostics::ToolHelp as Win32ToolHelp;
+    use windows::Win32::System::ProcessStatus as Win32ProcessStatus;
+    use windows::Win32::System::Threading as Win32Threading;
+
+    use super::Sample;
+    use crate::process;
+
+    pub fn sample(pids: &[u32]) -> Vec<Sample> {
+        let threads = thread_counts().unwrap_or_default();
+        pids.iter()
+            .filter_map(|&pid| process(pid, threads.get(&pid).copied().unwrap_or_default()).ok())
+            .collect()
+    }
+
+    fn filetime(filetime: Win32Foundation::FILETIME) -> u64 {
+        (filetime.dwHighDateTime as u64) << 32 | filetime.dwLowDateTime as u64
+    }
+
+    fn process(pid: u32, threads: u64) -> io::Result<Sample> {
+        unsafe {
+            let handle = Win32Threading::OpenProcess(
+                Win32Threading::PROCESS_QUERY_LIMITED_INFORMATION | Win32Threading::PROCESS_VM_READ,
+                false,
+                pid,
+            )?;
+            let handle = OwnedHandle::from_raw_handle(handle.0);
+            let handle = Win32Foundation::HANDLE(handle.as_raw_handle());
+
+            let mut creation = Win32Foundation::FILETIME::default();
+            let mut exit = Win32Foundation::FILETIME::default();
+            let mut kernel = Win32Foundation::FILETIME::default();
+            let mut user = Win32Foundation::FILETIME::default();
+            Win32Threading::GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user)?;
+
+            let mut counters = Win32ProcessStatus::PROCESS_MEMORY_COUNTERS {
+                cb: mem::size_of::<Win32ProcessStatus::PROCESS_MEMORY_COUNTERS>() as u32,
+                ..Default::default()
+            };
+            Win32ProcessStatus::GetProcessMemoryInfo(handle, &mut counters, counters.cb)?;
+
+            let mut handles = 0u32;
+            Win32Threading::GetProcessHandleCount(handle, &mut handles)?;
+
+            Ok(Sample {
+                // Process times count 100ns intervals.
+                cpu_time: Duration::from_nanos((filetime(kernel) + filetime(user)) * 100),
+                memory: counters.WorkingSetSize as u64,
+                handles: handles as u64,
+                threads,
+                started: process::filetime_to_local(filetime(creation)),
+            })
+        }
+    }
+
+    /// Thread count of every running process, from a single snapshot.
+    fn thread_counts() -> io::Result<HashMap<u32, u64>> {
+        let mut counts = HashMap::new();
+        unsafe {
+            let snapshot = Win32ToolHelp::CreateToolhelp32Snapshot(Win32ToolHelp::TH32CS_SNAPPROCESS, 0)?;
+            let owned_snapshot = OwnedHandle::from_raw_handle(snapshot.0);
+            let snapshot = Win32Foundation::HANDLE(owned_snapshot.as_raw_handle());
+
+            let mut entry = Win32ToolHelp::PROCESSENTRY32W {
+                dwSize: mem::size_of::<Win32ToolHelp::PROCESSENTRY32W>() as u32,
+                ..Default::default()
+            };
+            let mut result = Win32ToolHelp::Process32FirstW(snapshot, &mut entry);
+            while result.is_ok() {
+                counts.insert(entry.th32ProcessID, entry.cntThreads as u64);
+                result = Win32ToolHelp::Process32NextW(snapshot, &mut entry);
+            }
+        }
+        Ok(counts)
+    }
+}
@@ -0,0 +1,77 @@
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use crate::config::Service;
+use crate::history::Cause;
+
+const DEFAULT_RESTART_SEC: u64 = 1;
+const DEFAULT_RESTART_MAX_DELAY_SEC: u64 = 60;
+const DEFAULT_START_LIMIT_INTERVAL_SEC: u64 = 60;
+const DEFAULT_START_LIMIT_BURST: usize = 5;
+
+static RESTARTS: Lazy<Mutex<HashMap<String, Restarts>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Automatic restarts of a unit, for its backoff and start limit.
+#[derive(Debug, Default)]
+struct Restarts {
+    /// When the restarts within the start limit interval happened.
+    recent: VecDeque<Instant>,
+    /// When the scheduled restart is due and what caused it.
+    due: Option<(Instant, Cause)>,
+    /// The start limit was hit, the unit is not restarted again until it is
+    /// started or stopped by hand.
+    limited: bool,
+}
+
+/// Schedules a restart of `name` and returns how long it waits for it:
+/// `restart_sec`, doubled for every restart within the start limit interval
+/// up to `restart_max_delay_sec`. Returns `None` if the unit has already been
+/// restarted `start_limit_burst` times within that interval.
+pub fn schedule(name: &str, service: &Service, cause: Cause) -> Option<Duration> {
+    let interval = Duration::from_secs(service.start_limit_interval_sec.unwrap_or(DEFAULT_START_LIMIT_INTERVAL_SEC));
+    let burst = service.start_limit_burst.unwrap_or(DEFAULT_START_LIMIT_BURST);
+    let restart_sec = service.restart_sec.unwrap_or(DEFAULT_RESTART_SEC);
+    let max_delay_sec = service.restart_max_delay_sec.unwrap_or(DEFAULT_RESTART_MAX_DELAY_SEC);
+
+    let mut restarts = RESTARTS.lock().unwrap();
+    let restarts = restarts.entry(name.to_string()).or_default();
+    let now = Instant::now();
+    while restarts.recent.front().is_some_and(|&time| now.duration_since(time) > interval) {
+        restarts.recent.pop_front();
+    }
+    if restarts.recent.len() >= burst {
+        restarts.limited = true;
+        restarts.due = None;
+        return None;
+    }
+
+    let factor = 1u64.checked_shl(restarts.recent.len() as u32).unwrap_or(u64::MAX);
+    let delay = Duration::from_secs(restart_sec.saturating_mul(factor).min(max_delay_sec.max(restart_sec)));
+    restarts.due = Some((now + delay, cause));
+    Some(delay)
+}
+
+/// Returns the cause of the scheduled restart of `name` if it is due, and
+/// counts it as done.
+pub fn take_due(name: &str) -> Option<Cause> {
+    let mut restarts = RESTARTS.lock().unwrap();
+    let restarts = restarts.get_mut(name)?;
+    let (due, cause) = restarts.due?;
+    if Instant::now() < due {
+        return None;
+    }
+    restarts.due = None;
+    restarts.recent.push_back(Instant::now());
+    Some(cause)
+}
+
+/// Whether `name` waits for a scheduled restart or hit its start limit.
+pub fn is_pending(name: &str) -> bool {
+    RESTARTS.lock().unwrap().get(name).is_some_and(|restarts| restarts.due.is_some() || restarts.limited)
+}
+
+/// Forgets the restarts of `name`, when it is started or stopped by hand.
+pub fn reset(name: &str) {
+    RESTARTS.lock().unwrap().remove(name);
+}
@@ -1,19 +1,47 @@
-use crate::{SERVICE_PIPE_NAME_WIDE, config, process, Cli, Commands, client, pipe};
+use crate::{SERVICE_PIPE_NAME_WIDE, config, process, condition, health, history, journal, logger, notify, output, resources, restart, unit_file, unit_state, Cli, Commands, client, pipe};
+use crate::history::Cause;
+use crate::unit_state::{ActiveState, SubState};
 
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chrono::Local;
 
 use std::sync::Mutex;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 
 static WORKER_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
 
+static SUPERVISOR_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+static HEALTH_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
 static STOP_TOKEN: AtomicBool = AtomicBool::new(false);
 
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records shown before new ones with `logs -f` unless `-n` is given.
+const FOLLOW_LINES: usize = 10;
+
 
 pub fn stop() {
     STOP_TOKEN.store(true, Ordering::Relaxed);
-    log::info!("The closing message `{}`", client::run(&Cli::default()));
+
+    // Join the supervisor and health checks first so they cannot restart
+    // units while they are stopped.
+    for thread in [&SUPERVISOR_THREAD, &HEALTH_THREAD] {
+        if let Some(handle) = thread.lock().unwrap().take() {
+            handle.join().ok();
+        }
+    }
+
+    // Units started by requests, restarts or the startup may still be
+    // spawning, they are either stopped below or never spawned.
+    process::close();
+    shutdown_services();
+
+    log::info!(event = "shutdown"; "The closing message `{}`", client::run(&Cli::default()));
 
     let mut handle_guard = WORKER_THREAD.lock().unwrap();
     if let Some(handle) = handle_guard.take() {
@@ -21,25 +49,108 @@ pub fn stop() {
     }
 }
 
+/// Applies the configured shutdown policy to the managed services.
+fn shutdown_services() {
+    match config::settings().shutdown {
+        config::ShutdownPolicy::LeaveRunning => {
+            log::info!(event = "shutdown"; "Leaving services running according to the shutdown policy.");
+        }
+        config::ShutdownPolicy::StopAll => {
+            let running = process::names();
+
+            // Reverse dependency order, then anything without a loaded config.
+            let mut order: Vec<String> = config::start_order().into_iter().rev()
+                .filter(|name| running.contains(name))
+                .collect();
+            for name in running {
+                if !order.contains(&name) {
+                    order.push(name);
+                }
+            }
+
+            for name in order {
+                match stop_unit(&name, Cause::Shutdown) {
+                    Ok(()) => log::info!(unit = name.as_str(), event = "stopped"; "Service {} stopped on shutdown.", name),
+                    Err(e) => log::error!(unit = name.as_str(), event = "stop_failed"; "Failed to stop service {} on shutdown: {:?}", name, e),
+                }
+            }
+        }
+    }
+}
+
 pub fn run() {
     let mut handle_guard = WORKER_THREAD.lock().unwrap();
 
     if handle_guard.is_none() {
         let handle = thread::spawn(move || {
             server_init();
+            // Units may take until their start timeout to become ready, so
+            // they are started apart from the requests.
+            thread::spawn(start_units);
             while !STOP_TOKEN.load(Ordering::Relaxed) {
-                pipe::listen(SERVICE_PIPE_NAME_WIDE, |recv| {
-                    handle_pipe(recv).into_bytes()
-                }).unwrap_or_else(|e| {
-                    log::error!("Error listening on pipe: {:?}", e);
+                pipe::accept(SERVICE_PIPE_NAME_WIDE).and_then(serve).unwrap_or_else(|e| {
+                    log::error!(event = "pipe_failed"; "Error listening on pipe: {:?}", e);
                 });
             }
-            log::info!("Worker thread stopped.");
+            log::info!(event = "thread_stopped"; "Worker thread stopped.");
+        });
+        *handle_guard = Some(handle);
+    }
+
+    let mut handle_guard = SUPERVISOR_THREAD.lock().unwrap();
+
+    if handle_guard.is_none() {
+        let handle = thread::spawn(move || {
+            while !STOP_TOKEN.load(Ordering::Relaxed) {
+                thread::sleep(SUPERVISE_INTERVAL);
+                supervise();
+            }
+            log::info!(event = "thread_stopped"; "Supervisor thread stopped.");
+        });
+        *handle_guard = Some(handle);
+    }
+
+    // Probes may take up to their timeout, so they run apart from the supervisor.
+    let mut handle_guard = HEALTH_THREAD.lock().unwrap();
+
+    if handle_guard.is_none() {
+        let handle = thread::spawn(move || {
+            while !STOP_TOKEN.load(Ordering::Relaxed) {
+                thread::sleep(SUPERVISE_INTERVAL);
+                check_health();
+            }
+            log::info!(event = "thread_stopped"; "Health check thread stopped.");
         });
         *handle_guard = Some(handle);
     }
 }
 
+/// Replies to the request of a client from a thread of its own, as a start
+/// may wait for the unit to become ready and following the logs keeps the
+/// connection open. Only the requesting client waits meanwhile.
+fn serve(mut connection: pipe::Connection) -> crate::Result<()> {
+    let Some(request) = connection.read()? else {
+        return Ok(());
+    };
+    match serde_json::from_slice::<Cli>(&request) {
+        Ok(Cli { command: Some(Commands::Logs { name, lines, since, until, grep, follow }) }) => {
+            thread::spawn(move || {
+                if let Err(e) = stream_logs(connection, &name, lines, since.as_deref(), until.as_deref(), grep, follow) {
+                    log::debug!(unit = name.as_str(), event = "logs_closed"; "Stopped streaming the logs of service {}: {:?}", name, e);
+                }
+            });
+            Ok(())
+        }
+        _ => {
+            thread::spawn(move || {
+                if let Err(e) = connection.write(handle_pipe(&request).as_bytes()) {
+                    log::debug!(event = "reply_failed"; "Failed to reply to a client: {:?}", e);
+                }
+            });
+            Ok(())
+        }
+    }
+}
 
 fn handle_pipe(recv: &[u8]) -> String {
 
@@ -50,49 +161,54 @@ fn handle_pipe(recv: &[u8]) -> String {
             match cli.command {
                 Some(cmd) => match cmd {
                     Commands::Start { ref name } => {
-                        config::get(name, |config| {
-                            match process::spawn(name, &config.service) {
-                                Ok(()) => format!("Service `{}` started successfully.", name),
-                                Err(e) => format!("Failed to start service `{}`: {:?}", name, e)
+                        restart::reset(name);
+                        start_requirements(name, &mut Vec::new());
+                        match start_unit(name, Cause::User) {
+                            Some(Ok(Started::Started)) => format!("Service `{}` started successfully.", name),
+                            Some(Ok(Started::Skipped(condition))) => format!("Service `{}` skipped, condition not met: {}", name, condition),
+                            Some(Err(process::Error::ExitedDuringStart(code, stderr))) if stderr.is_empty() => {
+                                format!("Failed to start service `{}`: exited with code {} during start.", name, code)
                             }
-                        }).unwrap_or(format!("Cannot Find Service `{}`", name))
-                    }
-                    Commands::Status { ref name } => (|name| {
-                        let cfg = match config::get(name, |config| {
-                            config.clone()
-                        }) {
-                            Some(cfg) => cfg,
-                            None => {
-                                return format!("Failed to get config for service `{}`", name);
+                            Some(Err(process::Error::ExitedDuringStart(code, stderr))) => {
+                                format!("Failed to start service `{}`: exited with code {} during start, stderr:\n{}", name, code, stderr)
                             }
-                        };
-
-                        let mut ret = String::new();
-
-                        ret.push_str(&format!("{} - {}\n\n", name, cfg.unit.description.unwrap_or("Not provided description".to_string())));
-
-                        ret.push_str(&format!("{:<7}:{:?} \n", "Type", cfg.service.style));
-                        ret.push_str(&format!("{:<7}:{}", "Status", 
-                            match process::check(name) {
-                                Ok(_) => format!("Running"),
-                                Err(e) => format!("{:?}", e)
-                            }
-                        ));
-                        
-                        ret
-                    }) (&name),
+                            Some(Err(e)) => format!("Failed to start service `{}`: {:?}", name, e),
+                            None => format!("Cannot Find Service `{}`", name)
+                        }
+                    }
+                    Commands::Status { ref name } => status_report(name),
+                    Commands::History { ref name } => history_report(name),
+                    Commands::List { resources } => list_report(resources),
+                    Commands::Export { ref name, ref format } => {
+                        match unit_file::export(name, format) {
+                            Ok(rendered) => rendered.trim_end().to_string(),
+                            Err(e) => format!("Failed to export `{}`: {}", name, e)
+                        }
+                    }
                     Commands::Stop { ref name } => {
-                        match process::stop(name) {
+                        match stop_unit(name, Cause::User) {
                             Ok(()) => format!("Service `{}` stopped successfully.", name),
                             Err(e) => format!("Failed to stop service `{}`: {:?}", name, e)
                         }
                     },
-                    Commands::ReloadConfig => {
-                        match config::load() {
-                            Ok(()) => format!("Configuration reloaded successfully."),
+                    Commands::ReloadConfig { apply } => {
+                        match config::reload() {
+                            Ok(diff) => {
+                                let settings = config::settings();
+                                logger::configure(&settings);
+                                journal::configure(&settings);
+                                reload_report(&diff, apply)
+                            }
                             Err(e) => format!("Error reloading configuration: {:?}", e)
                         }
                     }
+                    Commands::LogLevel { level, ref module } => {
+                        logger::set_level(module.as_deref(), level);
+                        match module {
+                            Some(module) => format!("Log level of module `{}` set to {}.", module, level),
+                            None => format!("Log level set to {}.", level),
+                        }
+                    }
                     _ => {
                         format!("Unknown command: {:?}", cmd)
                     }
@@ -108,31 +224,591 @@ fn handle_pipe(recv: &[u8]) -> String {
     }
 }
 
+fn status_report(name: &str) -> String {
+    let cfg = match config::get(name, |config| {
+        config.clone()
+    }) {
+        Some(cfg) => cfg,
+        None => {
+            return format!("Failed to get config for service `{}`", name);
+        }
+    };
+
+    refresh_state(name);
+
+    let mut ret = String::new();
+
+    ret.push_str(&format!("{} - {}\n\n", name, cfg.unit.description.unwrap_or("Not provided description".to_string())));
+
+    ret.push_str(&format!("{:<7}:{:?} \n", "Type", cfg.service.style));
+    ret.push_str(&format!("{:<7}:{}", "Active", unit_state::get(name)));
+    if let Some(status) = notify::get(name).status {
+        ret.push_str(&format!("\n{:<7}:{}", "Status", status));
+    }
+    if let Some(status) = health::get(name) {
+        ret.push_str(&format!("\n{:<7}:{} at {}", "Health",
+            match (status.healthy, &status.error) {
+                (true, None) => "healthy".to_string(),
+                (true, Some(e)) => format!("healthy, {} failed probe(s), last: {}", status.failures, e),
+                (false, e) => format!("unhealthy, {} failed probes, last: {}", status.failures, e.as_deref().unwrap_or_default()),
+            },
+            status.checked.format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+    // The job of a unit whose process exited has no members left.
+    if let Ok(members) = process::members(name)
+        && !members.is_empty()
+    {
+        let usage = resources::sample(&members);
+        let members: Vec<String> = members.iter().map(|pid| pid.to_string()).collect();
+        ret.push_str(&format!("\n{:<7}:{}", "PIDs", members.join(", ")));
+        if usage.processes > 0 {
+            ret.push_str(&format!("\n{:<7}:{} processes, {} threads, {} handles", "Tasks", usage.processes, usage.threads, usage.handles));
+            ret.push_str(&format!("\n{:<7}:{}", "Memory", resources::bytes(usage.memory)));
+            ret.push_str(&format!("\n{:<7}:{}", "CPU", resources::duration(usage.cpu_time)));
+        }
+        if let Some(uptime) = usage.uptime() {
+            ret.push_str(&format!("\n{:<7}:{}", "Uptime", resources::duration(uptime)));
+        }
+    }
+    if let Some(run) = history::runs(name).last() {
+        ret.push_str(&format!("\n{:<7}:{} since {}, started by {:?}", "Run",
+            run.outcome(),
+            run.start.format("%Y-%m-%d %H:%M:%S"),
+            run.started_by
+        ));
+    }
+    if let Some(run) = history::last_failure(name) {
+        ret.push_str(&format!("\n{:<7}:{} at {}", "Failure",
+            run.outcome(),
+            run.stop.unwrap_or(run.start).format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+    if let Some((time, outcome)) = condition::last(name) {
+        ret.push_str(&format!("\n{:<7}:{} at {}", "Check",
+            match outcome {
+                condition::Outcome::Passed => "passed".to_string(),
+                condition::Outcome::ConditionFailed(c) => format!("skipped, {} failed", c),
+                condition::Outcome::AssertionFailed(a) => format!("failed, {} failed", a),
+            },
+            time.format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+
+    let recent = output::recent(name);
+    if !recent.is_empty() {
+        ret.push('\n');
+        for record in recent {
+            ret.push_str(&format!("\n{}", record.line()));
+        }
+    }
+
+    ret
+}
+
+fn list_report(with_resources: bool) -> String {
+    let mut names = config::start_order();
+    for name in process::names() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return "No services configured.".to_string();
+    }
+    for name in &names {
+        refresh_state(name);
+    }
+
+    if !with_resources {
+        names.sort();
+        let mut ret = format!("{:<24}  {:<12}  {:<12}  {:>7}\n", "NAME", "ACTIVE", "SUB", "PID");
+        for name in names {
+            let state = unit_state::get(&name);
+            ret.push_str(&format!("{:<24}  {:<12}  {:<12}  {:>7}\n",
+                name,
+                state.active.to_string(),
+                state.sub.to_string(),
+                main_pid(&name).map(|pid| pid.to_string()).unwrap_or("-".to_string())
+            ));
+        }
+        return ret;
+    }
+
+    // Like top, the services using the most CPU since the previous listing
+    // come first.
+    let mut rows: Vec<(String, resources::Usage, Option<f64>)> = names.into_iter()
+        .map(|name| {
+            let usage = process::members(&name).map(|members| resources::sample(&members)).unwrap_or_default();
+            let cpu = resources::cpu_percent(&name, &usage);
+            (name, usage, cpu)
+        })
+        .collect();
+    rows.sort_by(|(a, _, a_cpu), (b, _, b_cpu)| {
+        b_cpu.unwrap_or(-1.0).total_cmp(&a_cpu.unwrap_or(-1.0)).then(a.cmp(b))
+    });
+
+    let mut ret = format!("{:<24}  {:<12}  {:>7}  {:>6}  {:>12}  {:>9}  {:>5}  {:>7}  {:>7}  {:>12}\n",
+        "NAME", "ACTIVE", "PID", "%CPU", "CPU TIME", "MEMORY", "PROCS", "THREADS", "HANDLES", "UPTIME");
+    for (name, usage, cpu) in rows {
+        let running = usage.processes > 0;
+        let column = |value: String| if running { value } else { "-".to_string() };
+        ret.push_str(&format!("{:<24}  {:<12}  {:>7}  {:>6}  {:>12}  {:>9}  {:>5}  {:>7}  {:>7}  {:>12}\n",
+            name,
+            unit_state::get(&name).active.to_string(),
+            main_pid(&name).map(|pid| pid.to_string()).unwrap_or("-".to_string()),
+            cpu.map(|cpu| format!("{:.1}", cpu)).unwrap_or("-".to_string()),
+            column(resources::duration(usage.cpu_time)),
+            column(resources::bytes(usage.memory)),
+            column(usage.processes.to_string()),
+            column(usage.threads.to_string()),
+            column(usage.handles.to_string()),
+            usage.uptime().map(resources::duration).unwrap_or("-".to_string())
+        ));
+    }
+    ret
+}
+
+fn history_report(name: &str) -> String {
+    let runs = history::runs(name);
+    if runs.is_empty() {
+        return format!("No recorded runs for service `{}`.", name);
+    }
+
+    let mut ret = format!("{:<19}  {:<19}  {:>7}  {:<13}  {}\n", "START", "STOP", "PID", "STARTED BY", "RESULT");
+    for run in runs.iter().rev() {
+        ret.push_str(&format!("{:<19}  {:<19}  {:>7}  {:<13}  {}\n",
+            run.start.format("%Y-%m-%d %H:%M:%S").to_string(),
+            run.stop.map(|stop| stop.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or("-".to_string()),
+            run.pid,
+            format!("{:?}", run.started_by),
+            run.outcome()
+        ));
+    }
+    ret
+}
+
+/// Writes the journal records of `name` to `connection`, then with `follow`
+/// every new one until the client disconnects or the manager stops.
+fn stream_logs(
+    mut connection: pipe::Connection,
+    name: &str,
+    lines: Option<usize>,
+    since: Option<&str>,
+    until: Option<&str>,
+    grep: Option<String>,
+    follow: bool,
+) -> crate::Result<()> {
+    if !config::journal_enabled() {
+        return connection.write(b"The journal is disabled in the settings.\n");
+    }
+
+    let mut filter = journal::Filter { grep, ..Default::default() };
+    for (value, target) in [(since, &mut filter.since), (until, &mut filter.until)] {
+        if let Some(value) = value {
+            match journal::parse_time(value) {
+                Some(time) => *target = Some(time),
+                None => return connection.write(format!("Invalid time `{}`\n", value).as_bytes()),
+            }
+        }
+    }
+
+    // Subscribe before reading the journal so no record written in between is missed.
+    let subscription = follow.then(journal::subscribe);
+    let records = match journal::query(name, &filter) {
+        Ok(records) => records,
+        Err(e) => return connection.write(format!("Failed to read the journal: {:?}\n", e).as_bytes()),
+    };
+    if records.is_empty() && !follow {
+        return connection.write(format!("No journal records for service `{}`.\n", name).as_bytes());
+    }
+
+    let lines = lines.or(follow.then_some(FOLLOW_LINES)).unwrap_or(usize::MAX);
+    let mut chunk = String::new();
+    for record in &records[records.len().saturating_sub(lines)..] {
+        chunk.push_str(&record.line());
+        chunk.push('\n');
+        if chunk.len() >= pipe::BUFFER_SIZE / 2 {
+            connection.write(chunk.as_bytes())?;
+            chunk.clear();
+        }
+    }
+    if !chunk.is_empty() {
+        connection.write(chunk.as_bytes())?;
+    }
+
+    let Some(subscription) = subscription else {
+        return Ok(());
+    };
+    let last = records.last().map(|record| record.ts);
+    while !STOP_TOKEN.load(Ordering::Relaxed) {
+        match subscription.recv_timeout(SUPERVISE_INTERVAL) {
+            Ok(record) => {
+                if filter.matches(name, &record) && last.is_none_or(|last| record.ts > last) {
+                    connection.write(format!("{}\n", record.line()).as_bytes())?;
+                }
+            }
+            // An empty message finds out whether the client is still there.
+            Err(RecvTimeoutError::Timeout) => connection.write(&[])?,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(())
+}
+
+fn reload_report(diff: &config::Diff, apply: bool) -> String {
+    if diff.is_empty() {
+        return "Configuration reloaded successfully, no changes.".to_string();
+    }
+
+    let mut ret = String::from("Configuration reloaded successfully.\n");
+    for (label, names) in [("Added", &diff.added), ("Removed", &diff.removed), ("Changed", &diff.changed)] {
+        if !names.is_empty() {
+            ret.push_str(&format!("{:<8}:{}\n", label, names.join(", ")));
+        }
+    }
+
+    if !apply {
+        return ret;
+    }
+
+    ret.push('\n');
+    for name in &diff.removed {
+        if process::check(name).is_ok() {
+            ret.push_str(&match stop_unit(name, Cause::Reload) {
+                Ok(()) => format!("Stopped removed service `{}`.\n", name),
+                Err(e) => format!("Failed to stop removed service `{}`: {:?}\n", name, e)
+            });
+        }
+    }
+
+    for name in &diff.changed {
+        if process::check(name).is_err() {
+            continue;
+        }
+        unit_state::set(name, ActiveState::Reloading, SubState::Reload, None);
+        let result = process::stop(name, config::stop_timeout(name), Cause::Reload)
+            .inspect_err(|e| unit_state::fail(name, format!("stop failed: {}", e)))
+            .and_then(|()| {
+                start_unit(name, Cause::Reload).unwrap_or(Err(process::Error::ProcessNotFound))
+            });
+        ret.push_str(&match result {
+            Ok(Started::Started) => format!("Restarted changed service `{}`.\n", name),
+            Ok(Started::Skipped(condition)) => format!("Stopped changed service `{}`, restart skipped: {}\n", name, condition),
+            Err(e) => format!("Failed to restart changed service `{}`: {:?}\n", name, e)
+        });
+    }
+
+    for name in &diff.added {
+        let is_startup = config::get(name, |config| {
+            config.service.style == config::ServiceType::Startup
+        }).unwrap_or(false);
+        if !is_startup {
+            continue;
+        }
+        match start_unit(name, Cause::Reload) {
+            Some(Ok(Started::Started)) => ret.push_str(&format!("Started new service `{}`.\n", name)),
+            Some(Ok(Started::Skipped(condition))) => ret.push_str(&format!("Skipped new service `{}`: {}\n", name, condition)),
+            Some(Err(e)) => ret.push_str(&format!("Failed to start new service `{}`: {:?}\n", name, e)),
+            None => {}
+        }
+    }
+
+    ret
+}
+
 fn server_init() {
     match config::load() {
         Ok(()) => {
-            log::info!("Configuration loaded successfully.");
+            log::info!(event = "config_loaded"; "Configuration loaded successfully.");
         }
         Err(err) => {
-            log::error!("Error loading configuration: {:?}", err);
+            log::error!(event = "config_failed"; "Error loading configuration: {:?}", err);
         }
     }
+    let settings = config::settings();
+    logger::configure(&settings);
+    journal::configure(&settings);
 
 
-    config::for_each(|config| {
-        let config::Config{unit, service, other} = config;
-        match service.style {
-            config::ServiceType::Startup => {
-                match process::spawn(&unit.name, service) {
-                    Ok(()) => log::info!("Service {} started successfully.", &unit.name),
-                    Err(e) => log::error!("Failed to start service {}: {:?}", &unit.name, e)
-                }
+    process::adopt();
+    for name in process::names() {
+        if process::check(&name).is_ok() {
+            unit_state::set(&name, ActiveState::Active, SubState::Running, Some("adopted".to_string()));
+            // Adopted processes still talk to the notify pipe they were started with.
+            if config::get(&name, |config| config::uses_notify(&config.service)).unwrap_or(false)
+                && let Err(e) = notify::open(&name)
+            {
+                log::error!(unit = name.as_str(), event = "notify_failed"; "Failed to listen for notifications of adopted service {}: {}", name, e);
             }
-            _ => {
-                log::info!("Service {} is of type {:?}", &unit.name, service.style);
+        }
+    }
+}
+
+/// Starts the `Startup` units in dependency order, each once the units
+/// before it are ready.
+fn start_units() {
+    for name in config::start_order() {
+        if STOP_TOKEN.load(Ordering::Relaxed) {
+            break;
+        }
+        let is_startup = config::get(&name, |config| {
+            config.service.style == config::ServiceType::Startup
+        }).unwrap_or(false);
+
+        if !is_startup || process::check(&name).is_ok() {
+            continue;
+        }
+
+        start_requirements(&name, &mut Vec::new());
+        match start_unit(&name, Cause::Startup) {
+            Some(Ok(Started::Started)) => log::info!(unit = name.as_str(), event = "started", pid = main_pid(&name); "Service {} started successfully.", name),
+            Some(Ok(Started::Skipped(condition))) => log::info!(unit = name.as_str(), event = "skipped"; "Service {} skipped, condition not met: {}", name, condition),
+            Some(Err(e)) => log::error!(unit = name.as_str(), event = "start_failed"; "Failed to start service {}: {:?}", name, e),
+            None => {}
+        }
+    }
+}
+
+enum Started {
+    Started,
+    Skipped(String),
+}
+
+/// Checks the conditions and assertions of `name` and spawns it if they hold,
+/// then waits for it to become ready if it has a `timeout_start_sec`.
+/// Returns `None` if no such unit is configured.
+fn start_unit(name: &str, cause: Cause) -> Option<Result<Started, process::Error>> {
+    if process::check(name).is_ok() {
+        return config::get(name, |_| Err(process::Error::ProcessAlreadyRunning));
+    }
+
+    let config = config::get(name, |config| config.clone())?;
+    unit_state::set(name, ActiveState::Activating, SubState::Start, None);
+    let result = match condition::evaluate(&config.unit) {
+        condition::Outcome::Passed => process::spawn(name, &config.service, cause).and_then(|()| {
+            match config::start_timeout(name) {
+                Some(timeout) => process::wait_ready(name, &config.service, timeout)
+                    .inspect_err(|e| if let process::Error::NotReady = e {
+                        process::stop(name, config::stop_timeout(name), Cause::StartTimeout).ok();
+                    }),
+                None => Ok(()),
             }
+        }).map(|()| Started::Started),
+        condition::Outcome::ConditionFailed(condition) => Ok(Started::Skipped(condition)),
+        condition::Outcome::AssertionFailed(assertion) => Err(process::Error::AssertionFailed(assertion)),
+    };
+
+    match &result {
+        Ok(Started::Started) => {
+            health::reset(name);
+            unit_state::set(name, ActiveState::Active, SubState::Running, None);
         }
-    });
+        Ok(Started::Skipped(condition)) => {
+            unit_state::set(name, ActiveState::Inactive, SubState::Dead, Some(format!("{} not met", condition)));
+        }
+        // Stopped while waiting for it to become ready, the stop set the state.
+        Err(process::Error::ProcessNotFound) => {}
+        Err(e) => unit_state::fail(name, format!("start failed: {}", e)),
+    }
+    Some(result)
+}
 
+/// Stops `name` and moves it to `inactive`, or `failed` if it could not be stopped.
+fn stop_unit(name: &str, cause: Cause) -> Result<(), process::Error> {
+    // A unit waiting for its restart is stopped by cancelling the restart.
+    if restart::is_pending(name) {
+        restart::reset(name);
+        if let Err(process::Error::ProcessNotFound) = process::check(name) {
+            unit_state::set(name, ActiveState::Inactive, SubState::Dead, Some(format!("stopped by {:?}", cause)));
+            return Ok(());
+        }
+    }
+    if let Err(process::Error::ProcessNotFound) = process::check(name) {
+        return Err(process::Error::ProcessNotFound);
+    }
+
+    unit_state::set(name, ActiveState::Deactivating, SubState::Stop, None);
+    let result = process::stop(name, config::stop_timeout(name), cause);
+    match &result {
+        Ok(()) => unit_state::set(name, ActiveState::Inactive, SubState::Dead, Some(format!("stopped by {:?}", cause))),
+        Err(e) => unit_state::fail(name, format!("stop failed: {}", e)),
+    }
+    result
 }
 
+/// Moves a running unit whose process exited on its own to `inactive` or
+/// `failed` depending on whether its exit code counts as success.
+fn refresh_state(name: &str) {
+    if let Err(process::Error::ProcessExited(code)) = process::check(name)
+        && matches!(unit_state::get(name).active, ActiveState::Active | ActiveState::Deactivating | ActiveState::Reloading)
+    {
+        let result = format!("exited with code {}", code);
+        if process::is_success(name, code) {
+            unit_state::set(name, ActiveState::Inactive, SubState::Dead, Some(result));
+        } else {
+            unit_state::fail(name, result);
+        }
+    }
+}
+
+/// Starts the units listed in `requires` of `name` that are not running yet.
+fn start_requirements(name: &str, visited: &mut Vec<String>) {
+    visited.push(name.to_string());
+
+    let requires = config::get(name, |config| {
+        config.unit.requires.clone().unwrap_or_default()
+    }).unwrap_or_default();
+
+    for dep in requires {
+        if visited.contains(&dep) || process::check(&dep).is_ok() {
+            continue;
+        }
+        start_requirements(&dep, visited);
+        match start_unit(&dep, Cause::Requirement) {
+            Some(Ok(Started::Started)) => log::info!(unit = dep.as_str(), event = "started", pid = main_pid(&dep); "Service {} started as a requirement of {}.", dep, name),
+            Some(Ok(Started::Skipped(condition))) => log::info!(unit = dep.as_str(), event = "skipped"; "Service {} required by {} skipped, condition not met: {}", dep, name, condition),
+            Some(Err(e)) => log::error!(unit = dep.as_str(), event = "start_failed"; "Failed to start service {} required by {}: {:?}", dep, name, e),
+            None => log::error!(unit = dep.as_str(), event = "not_configured"; "Service {} required by {} is not configured.", dep, name),
+        }
+    }
+}
+
+/// Records exits of managed processes, stops hung units and restarts
+/// exited units according to their restart policy.
+fn supervise() {
+    for name in process::names() {
+        refresh_state(&name);
+    }
+
+    check_watchdogs();
+
+    for name in config::start_order() {
+        if let Some(cause) = restart::take_due(&name) {
+            restart_unit(&name, cause);
+            continue;
+        }
+        // Waiting for its restart, or starting already.
+        if restart::is_pending(&name) || unit_state::get(&name).active == ActiveState::Activating {
+            continue;
+        }
+
+        let restart = config::get(&name, |config| config.service.restart.clone()).flatten();
+        let Some(restart) = restart else {
+            continue;
+        };
+
+        if let Err(process::Error::ProcessExited(code)) = process::check(&name) {
+            if !restart.should_restart(process::is_success(&name, code)) || config::prevents_restart(&name, code) {
+                continue;
+            }
+            log::info!(unit = name.as_str(), event = "exited", exit_code = code; "Service {} exited with code {}.", name, code);
+            schedule_restart(&name, format!("exited with code {}", code), Cause::RestartPolicy);
+        }
+    }
+}
+
+/// Restarts `name` once its restart delay has passed, or makes it `failed`
+/// if it has been restarted too often.
+fn schedule_restart(name: &str, reason: String, cause: Cause) {
+    let Some(service) = config::get(name, |config| config.service.clone()) else {
+        return;
+    };
+    match restart::schedule(name, &service, cause) {
+        Some(delay) => {
+            log::info!(unit = name, event = "restart_scheduled"; "Restarting service {} in {}s.", name, delay.as_secs());
+            unit_state::set(name, ActiveState::Activating, SubState::AutoRestart, Some(format!("{}, restarting in {}s", reason, delay.as_secs())));
+        }
+        None => {
+            log::error!(unit = name, event = "start_limit_hit"; "Service {} restarted too often, giving up.", name);
+            unit_state::fail(name, format!("{}, start limit hit", reason));
+        }
+    }
+}
+
+/// Stops active units that have not sent `WATCHDOG=1` within their
+/// `watchdog_sec`, and restarts them if their policy restarts failed units.
+fn check_watchdogs() {
+    for name in process::names() {
+        let Some(watchdog_sec) = config::get(&name, |config| config.service.watchdog_sec).flatten() else {
+            continue;
+        };
+        let state = unit_state::get(&name);
+        if state.active != ActiveState::Active {
+            continue;
+        }
+
+        // The timer starts when the unit becomes active.
+        let last = notify::get(&name).watchdog.map_or(state.since, |last| last.max(state.since));
+        if Local::now() - last < chrono::Duration::seconds(watchdog_sec as i64) {
+            continue;
+        }
+
+        log::warn!(unit = name.as_str(), event = "watchdog_timeout", pid = main_pid(&name); "Service {} sent no WATCHDOG=1 for {} seconds, stopping it.", name, watchdog_sec);
+        // A hung process will not react to a close request, kill it right away.
+        let result = process::stop(&name, Duration::ZERO, Cause::Watchdog);
+        unit_state::fail(&name, "watchdog timeout".to_string());
+        if let Err(e) = result {
+            log::error!(unit = name.as_str(), event = "stop_failed"; "Failed to stop hung service {}: {:?}", name, e);
+            continue;
+        }
+
+        let restart = config::get(&name, |config| config.service.restart.clone()).flatten();
+        if restart.is_some_and(|restart| restart.should_restart(false)) {
+            schedule_restart(&name, "watchdog timeout".to_string(), Cause::RestartPolicy);
+        }
+    }
+}
+
+/// Probes the active units with a `[health]` section whose interval has
+/// passed, and restarts those that became unhealthy if they ask for it.
+fn check_health() {
+    for name in process::names() {
+        let Some(health) = config::get(&name, |config| config.health.clone()).flatten() else {
+            continue;
+        };
+        let state = unit_state::get(&name);
+        if state.active != ActiveState::Active {
+            continue;
+        }
+
+        // The first probe is one interval after the unit became active.
+        let last = health::get(&name).map_or(state.since, |status| status.checked.max(state.since));
+        if (Local::now() - last).to_std().unwrap_or_default() < health::interval(&health) {
+            continue;
+        }
+
+        if !health::check(&name, &health) || health.restart != Some(true) {
+            continue;
+        }
+        log::warn!(unit = name.as_str(), event = "unhealthy", pid = main_pid(&name); "Service {} is unhealthy, restarting.", name);
+        if let Err(e) = process::stop(&name, config::stop_timeout(&name), Cause::HealthCheck) {
+            unit_state::fail(&name, format!("stop failed: {}", e));
+            log::error!(unit = name.as_str(), event = "stop_failed"; "Failed to stop unhealthy service {}: {:?}", name, e);
+            continue;
+        }
+        schedule_restart(&name, "unhealthy".to_string(), Cause::HealthCheck);
+    }
+}
+
+/// The main process of `name`, for the log context.
+fn main_pid(name: &str) -> Option<u32> {
+    process::get(name, |process| process.pid).ok()
+}
+
+/// Starts `name` again from a thread of its own, so waiting for it to become
+/// ready holds up neither the supervisor nor the health checks.
+fn restart_unit(name: &str, cause: Cause) {
+    let name = name.to_string();
+    thread::spawn(move || {
+        if STOP_TOKEN.load(Ordering::Relaxed) {
+            return;
+        }
+        match start_unit(&name, cause) {
+            Some(Ok(Started::Started)) => log::info!(unit = name.as_str(), event = "restarted", pid = main_pid(&name); "Service {} restarted successfully.", name),
+            Some(Ok(Started::Skipped(condition))) => log::info!(unit = name.as_str(), event = "skipped"; "Service {} restart skipped, condition not met: {}", name, condition),
+            Some(Err(e)) => log::error!(unit = name.as_str(), event = "restart_failed"; "Failed to restart service {}: {:?}", name, e),
+            None => {}
+        }
+    });
+}
@@ -0,0 +1,722 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use clap::ValueEnum;
+use serde::{Serialize, Deserialize};
+use crate::config::{self, Config, Error, Input, KillMode, Output, RestartPolicy, Service, ServiceType, Unit};
+
+/// Parses a systemd `.service` file into a `Config`.
+///
+/// Returns the config together with a description of every directive that
+/// has no equivalent here and was therefore dropped.
+pub fn parse(content: &str, name: &str) -> Result<(Config, Vec<String>), Error> {
+    let mut unit = Unit {
+        name: name.to_string(),
+        description: None,
+        ..Default::default()
+    };
+    let mut style = ServiceType::Simple;
+    let mut notify = None;
+    let mut exec_start: Option<Vec<String>> = None;
+    let mut env: HashMap<String, String> = HashMap::new();
+    let mut working_directory = None;
+    let mut stdin = None;
+    let mut stdin_data: Option<String> = None;
+    let mut stdout = None;
+    let mut stderr = None;
+    let mut restart = None;
+    let mut restart_sec = None;
+    let mut start_limit_interval_sec = None;
+    let mut start_limit_burst = None;
+    let mut timeout_stop_sec = None;
+    let mut timeout_start_sec = None;
+    let mut watchdog_sec = None;
+    let mut kill_mode = None;
+    let mut success_exit_status = None;
+    let mut restart_prevent_exit_status = None;
+    let mut untranslated = Vec::new();
+
+    let mut section = String::new();
+    for line in logical_lines(content) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_string();
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(Error::Parse(format!("invalid line `{}`", line))),
+        };
+        let directive = format!("[{}] {}={}", section, key, value);
+
+        match (section.as_str(), key) {
+            ("Unit", "Description") => unit.description = Some(value.to_string()),
+            ("Unit", "After") | ("Unit", "Requires") => {
+                let target = if key == "After" { &mut unit.after } else { &mut unit.requires };
+                for dep in value.split_whitespace() {
+                    match dep.strip_suffix(".service") {
+                        Some(dep) => target.get_or_insert_with(Vec::new).push(dep.to_string()),
+                        None => untranslated.push(format!("[Unit] {}={} (only .service units are supported)", key, dep)),
+                    }
+                }
+            }
+            ("Unit", "ConditionPathExists") => push(&mut unit.condition_path_exists, value),
+            ("Unit", "ConditionFileNotEmpty") => push(&mut unit.condition_file_not_empty, value),
+            ("Unit", "ConditionEnvironment") => push(&mut unit.condition_env, value),
+            ("Unit", "AssertPathExists") => push(&mut unit.assert_path_exists, value),
+            ("Unit", "AssertFileNotEmpty") => push(&mut unit.assert_file_not_empty, value),
+            ("Unit", "AssertEnvironment") => push(&mut unit.assert_env, value),
+            ("Service", "ExecStart") => {
+                if value.is_empty() {
+                    exec_start = None;
+                    continue;
+                }
+                if exec_start.is_some() {
+                    untranslated.push(format!("{} (only one ExecStart is supported)", directive));
+                    continue;
+                }
+                let mut words = split_words(value)?;
+                if let Some(first) = words.first_mut() {
+                    let program = first.trim_start_matches(['@', '-', ':', '+', '!']);
+                    if program.len() != first.len() {
+                        untranslated.push(format!("[Service] ExecStart prefix `{}`", &first[..first.len() - program.len()]));
+                        *first = program.to_string();
+                    }
+                }
+                exec_start = Some(words);
+            }
+            ("Service", "Environment") => {
+                for word in split_words(value)? {
+                    match word.split_once('=') {
+                        Some((k, v)) => {
+                            env.insert(k.to_string(), v.to_string());
+                        }
+                        None => untranslated.push(format!("[Service] Environment={} (missing `=`)", word)),
+                    }
+                }
+            }
+            ("Service", "WorkingDirectory") => {
+                let dir = value.trim_start_matches('-');
+                if dir.starts_with('~') {
+                    untranslated.push(directive);
+                } else {
+                    working_directory = Some(dir.to_string());
+                }
+            }
+            ("Service", "StandardInput") => {
+                if let Some(path) = value.strip_prefix("file:") {
+                    stdin = Some(Input::File(path.to_string()));
+                } else {
+                    match value {
+                        "null" => stdin = Some(Input::Null),
+                        "data" => stdin = Some(Input::Data),
+                        _ => untranslated.push(directive),
+                    }
+                }
+            }
+            ("Service", "StandardInputText") => {
+                if value.is_empty() {
+                    stdin_data = None;
+                } else {
+                    let data = stdin_data.get_or_insert_with(String::new);
+                    data.push_str(value);
+                    data.push('\n');
+                }
+            }
+            ("Service", "StandardOutput") | ("Service", "StandardError") => {
+                let target = if key == "StandardOutput" { &mut stdout } else { &mut stderr };
+                if let Some(path) = value.strip_prefix("file:") {
+                    *target = Some(Output::File(path.to_string()));
+                } else if let Some(path) = value.strip_prefix("append:") {
+                    *target = Some(Output::Append(path.to_string()));
+                } else {
+                    match value {
+                        "journal" => *target = Some(Output::Journal),
+                        "null" => *target = Some(Output::Null),
+                        // For StandardError `inherit` means the same as StandardOutput.
+                        "inherit" if key == "StandardError" => *target = Some(Output::Stdout),
+                        _ => untranslated.push(directive),
+                    }
+                }
+            }
+            ("Service", "Restart") => {
+                restart = match value {
+                    "no" => Some(RestartPolicy::No),
+                    "always" => Some(RestartPolicy::Always),
+                    "on-success" => Some(RestartPolicy::OnSuccess),
+                    "on-failure" => Some(RestartPolicy::OnFailure),
+                    "on-abnormal" | "on-abort" | "on-watchdog" => {
+                        untranslated.push(format!("{} (treated as on-failure)", directive));
+                        Some(RestartPolicy::OnFailure)
+                    }
+                    _ => {
+                        untranslated.push(directive);
+                        None
+                    }
+                };
+            }
+            ("Service", "TimeoutStopSec") | ("Service", "TimeoutStartSec") | ("Service", "WatchdogSec")
+            | ("Service", "RestartSec") | ("Unit", "StartLimitIntervalSec") => {
+                let target = match key {
+                    "TimeoutStopSec" => &mut timeout_stop_sec,
+                    "TimeoutStartSec" => &mut timeout_start_sec,
+                    "RestartSec" => &mut restart_sec,
+                    "StartLimitIntervalSec" => &mut start_limit_interval_sec,
+                    _ => &mut watchdog_sec,
+                };
+                match value.trim_end_matches('s').parse::<u64>() {
+                    Ok(secs) => *target = Some(secs),
+                    Err(_) => untranslated.push(directive),
+                }
+            }
+            ("Unit", "StartLimitBurst") => match value.parse::<usize>() {
+                Ok(burst) => start_limit_burst = Some(burst),
+                Err(_) => untranslated.push(directive),
+            },
+            ("Service", "KillMode") => {
+                kill_mode = match value {
+                    "control-group" => Some(KillMode::ControlGroup),
+                    "process" => Some(KillMode::Process),
+                    "mixed" => Some(KillMode::Mixed),
+                    _ => {
+                        untranslated.push(directive);
+                        None
+                    }
+                };
+            }
+            ("Service", "SuccessExitStatus") | ("Service", "RestartPreventExitStatus") => {
+                let target = if key == "SuccessExitStatus" { &mut success_exit_status } else { &mut restart_prevent_exit_status };
+                if value.is_empty() {
+                    *target = None;
+                    continue;
+                }
+                for word in value.split_whitespace() {
+                    match word.parse::<u32>() {
+                        Ok(code) => target.get_or_insert_with(Vec::new).push(code),
+                        Err(_) => untranslated.push(format!("[Service] {}={} (only exit codes are supported)", key, word)),
+                    }
+                }
+            }
+            ("Service", "Type") if value == "simple" || value == "exec" => {}
+            ("Service", "Type") if value == "notify" => notify = Some(true),
+            ("Install", "WantedBy") => style = ServiceType::Startup,
+            _ => untranslated.push(directive),
+        }
+    }
+
+    // TimeoutStartSec is a readiness deadline, while `timeout_start_sec` of
+    // a unit without `notify` is how long it must stay alive.
+    if notify != Some(true)
+        && let Some(secs) = timeout_start_sec.take()
+    {
+        untranslated.push(format!("[Service] TimeoutStartSec={} (only translated for Type=notify)", secs));
+    }
+
+    let mut words = exec_start
+        .filter(|words| !words.is_empty())
+        .ok_or_else(|| Error::Parse("missing [Service] ExecStart".to_string()))?
+        .into_iter();
+    let path = words.next().unwrap_or_default();
+    let args: Vec<String> = words.collect();
+
+    let service = Service {
+        style,
+        path,
+        args: if args.is_empty() { None } else { Some(args) },
+        env: if env.is_empty() { None } else { Some(env) },
+        stdin,
+        stdin_data,
+        stdout,
+        stderr,
+        log_timestamps: None,
+        log_max_size: None,
+        log_max_age_sec: None,
+        log_max_files: None,
+        log_compress: None,
+        working_directory,
+        restart,
+        restart_sec,
+        restart_max_delay_sec: None,
+        start_limit_interval_sec,
+        start_limit_burst,
+        timeout_stop_sec,
+        timeout_start_sec,
+        kill_mode,
+        notify,
+        watchdog_sec,
+        success_exit_status,
+        restart_prevent_exit_status,
+        other: HashMap::new(),
+    };
+
+    Ok((Config { unit, service, health: None, other: HashMap::new() }, untranslated))
+}
+
+fn push(target: &mut Option<Vec<String>>, value: &str) {
+    target.get_or_insert_with(Vec::new).push(value.to_string());
+}
+
+/// Converts `path` to a TOML unit in the `configs` directory and returns a
+/// human readable report.
+pub fn import(path: &Path, name: Option<&str>) -> Result<String, Error> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => config::file_stem(path),
+    };
+    let (config, untranslated) = parse(&fs::read_to_string(path)?, &name)?;
+
+    let target = config::config_dir()?.join(format!("{}.toml", name));
+    if target.exists() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", target),
+        )));
+    }
+    fs::create_dir_all(config::config_dir()?)?;
+    fs::write(&target, toml::to_string(&config)?)?;
+
+    let mut ret = format!("Imported `{}` to {:?}.\n", name, target);
+    if !untranslated.is_empty() {
+        ret.push_str("Directives that could not be translated:\n");
+        for directive in untranslated {
+            ret.push_str(&format!("  {}\n", directive));
+        }
+    }
+    ret.push_str("Run `reload-config` to load it.");
+    Ok(ret)
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ExportFormat {
+    /// Linux systemd `.service` unit
+    Systemd,
+    /// Batch script of `nssm` commands
+    Nssm,
+    /// The unit in this tool's JSON format
+    Json,
+}
+
+/// Renders the configuration of `name` loaded in this process in another
+/// service manager's format.
+pub fn export(name: &str, format: &ExportFormat) -> Result<String, Error> {
+    let config = config::get(name, |config| config.clone()).ok_or_else(|| {
+        Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Cannot Find Service `{}`", name),
+        ))
+    })?;
+
+    Ok(match format {
+        ExportFormat::Systemd => render_systemd(&config),
+        ExportFormat::Nssm => render_nssm(&config),
+        ExportFormat::Json => format!("{}\n", serde_json::to_string_pretty(&config)?),
+    })
+}
+
+fn render_systemd(config: &Config) -> String {
+    let Config { unit, service, .. } = config;
+    let mut ret = String::from("[Unit]\n");
+
+    if let Some(description) = &unit.description {
+        ret.push_str(&format!("Description={}\n", escape_specifiers(description)));
+    }
+    for (key, deps) in [("After", &unit.after), ("Requires", &unit.requires)] {
+        if let Some(deps) = deps {
+            let deps: Vec<String> = deps.iter().map(|dep| format!("{}.service", dep)).collect();
+            ret.push_str(&format!("{}={}\n", key, deps.join(" ")));
+        }
+    }
+
+    for (key, values) in [
+        ("ConditionPathExists", &unit.condition_path_exists),
+        ("ConditionFileNotEmpty", &unit.condition_file_not_empty),
+        ("ConditionEnvironment", &unit.condition_env),
+        ("AssertPathExists", &unit.assert_path_exists),
+        ("AssertFileNotEmpty", &unit.assert_file_not_empty),
+        ("AssertEnvironment", &unit.assert_env),
+    ] {
+        for value in values.iter().flatten() {
+            ret.push_str(&format!("{}={}\n", key, escape_specifiers(value)));
+        }
+    }
+    if let Some(start_limit_interval_sec) = service.start_limit_interval_sec {
+        ret.push_str(&format!("StartLimitIntervalSec={}\n", start_limit_interval_sec));
+    }
+    if let Some(start_limit_burst) = service.start_limit_burst {
+        ret.push_str(&format!("StartLimitBurst={}\n", start_limit_burst));
+    }
+
+    ret.push_str(&format!("\n[Service]\nType={}\n", if service.notify == Some(true) { "notify" } else { "simple" }));
+    let command: Vec<String> = std::iter::once(&service.path)
+        .chain(service.args.iter().flatten())
+        .map(|word| quote_systemd(word))
+        .collect();
+    ret.push_str(&format!("ExecStart={}\n", command.join(" ")));
+
+    if let Some(env) = &service.env {
+        let mut env: Vec<_> = env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            ret.push_str(&format!("Environment={}\n", quote_systemd(&format!("{}={}", key, value))));
+        }
+    }
+    if let Some(working_directory) = &service.working_directory {
+        ret.push_str(&format!("WorkingDirectory={}\n", escape_specifiers(working_directory)));
+    }
+    if let Some(restart) = &service.restart {
+        ret.push_str(&format!("Restart={}\n", match restart {
+            RestartPolicy::No => "no",
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnSuccess => "on-success",
+            RestartPolicy::OnFailure => "on-failure",
+        }));
+    }
+    if let Some(restart_sec) = service.restart_sec {
+        ret.push_str(&format!("RestartSec={}\n", restart_sec));
+    }
+    // Without `notify` there is no readiness to wait for in systemd.
+    if service.notify == Some(true)
+        && let Some(timeout_start_sec) = service.timeout_start_sec
+    {
+        ret.push_str(&format!("TimeoutStartSec={}\n", timeout_start_sec));
+    }
+    if let Some(timeout_stop_sec) = service.timeout_stop_sec {
+        ret.push_str(&format!("TimeoutStopSec={}\n", timeout_stop_sec));
+    }
+    if let Some(watchdog_sec) = service.watchdog_sec {
+        ret.push_str(&format!("WatchdogSec={}\n", watchdog_sec));
+    }
+    if let Some(kill_mode) = &service.kill_mode {
+        ret.push_str(&format!("KillMode={}\n", match kill_mode {
+            KillMode::ControlGroup => "control-group",
+            KillMode::Process => "process",
+            KillMode::Mixed => "mixed",
+        }));
+    }
+    for (key, codes) in [
+        ("SuccessExitStatus", &service.success_exit_status),
+        ("RestartPreventExitStatus", &service.restart_prevent_exit_status),
+    ] {
+        if let Some(codes) = codes {
+            let codes: Vec<String> = codes.iter().map(|code| code.to_string()).collect();
+            ret.push_str(&format!("{}={}\n", key, codes.join(" ")));
+        }
+    }
+    if let Some(stdin) = &service.stdin {
+        ret.push_str(&format!("StandardInput={}\n", escape_specifiers(&stdin.to_string())));
+    }
+    for line in service.stdin_data.iter().flat_map(|data| data.lines()) {
+        ret.push_str(&format!("StandardInputText={}\n", escape_specifiers(line)));
+    }
+    for (key, output) in [("StandardOutput", &service.stdout), ("StandardError", &service.stderr)] {
+        match output {
+            // The output of the manager has no systemd equivalent.
+            None | Some(Output::Inherit) => {}
+            Some(Output::Stdout) => ret.push_str(&format!("{}=inherit\n", key)),
+            Some(output) => ret.push_str(&format!("{}={}\n", key, escape_specifiers(&output.to_string()))),
+        }
+    }
+
+    if service.style == ServiceType::Startup {
+        ret.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+    }
+    ret
+}
+
+fn render_nssm(config: &Config) -> String {
+    let Config { unit, service, .. } = config;
+    let name = quote_cmd(&unit.name);
+    let mut ret = String::from("@echo off\n");
+
+    ret.push_str(&format!("nssm install {} {}", name, quote_cmd(&service.path)));
+    for arg in service.args.iter().flatten() {
+        ret.push_str(&format!(" {}", quote_cmd(arg)));
+    }
+    ret.push('\n');
+
+    let mut set = |key: &str, value: &str| {
+        ret.push_str(&format!("nssm set {} {} {}\n", name, key, value));
+    };
+
+    if let Some(description) = &unit.description {
+        set("Description", &quote_cmd(description));
+    }
+    set("Start", match service.style {
+        ServiceType::Startup => "SERVICE_AUTO_START",
+        ServiceType::Simple => "SERVICE_DEMAND_START",
+    });
+    if let Some(deps) = &unit.requires {
+        set("DependOnService", &deps.iter().map(|dep| quote_cmd(dep)).collect::<Vec<_>>().join(" "));
+    }
+    if let Some(env) = &service.env {
+        let mut env: Vec<String> = env.iter().map(|(key, value)| quote_cmd(&format!("{}={}", key, value))).collect();
+        env.sort();
+        set("AppEnvironmentExtra", &env.join(" "));
+    }
+    if let Some(working_directory) = &service.working_directory {
+        set("AppDirectory", &quote_cmd(working_directory));
+    }
+    if let Some(Input::File(path)) = &service.stdin {
+        set("AppStdin", &quote_cmd(path));
+    }
+    // nssm writes both streams to one file when they share a path.
+    let stderr = match &service.stderr {
+        Some(Output::Stdout) => &service.stdout,
+        stderr => stderr,
+    };
+    for (key, output) in [("AppStdout", &service.stdout), ("AppStderr", stderr)] {
+        let (path, append) = match output {
+            Some(Output::File(path)) => (path, false),
+            Some(Output::Append(path)) => (path, true),
+            _ => continue,
+        };
+        set(key, &quote_cmd(path));
+        // OPEN_ALWAYS appends, CREATE_ALWAYS truncates.
+        set(&format!("{}CreationDisposition", key), if append { "4" } else { "2" });
+    }
+    if service.log_timestamps == Some(true) {
+        set("AppTimestampLog", "1");
+    }
+    if service.log_max_size.is_some() || service.log_max_age_sec.is_some() {
+        set("AppRotateFiles", "1");
+        set("AppRotateOnline", "1");
+        if let Some(log_max_size) = service.log_max_size {
+            set("AppRotateBytes", &log_max_size.to_string());
+        }
+        if let Some(log_max_age_sec) = service.log_max_age_sec {
+            set("AppRotateSeconds", &log_max_age_sec.to_string());
+        }
+    }
+    if let Some(timeout_start_sec) = service.timeout_start_sec {
+        set("AppThrottle", &(timeout_start_sec * 1000).to_string());
+    }
+    if let Some(restart) = &service.restart {
+        let (default, on_zero) = match restart {
+            RestartPolicy::No => ("Exit", "Exit"),
+            RestartPolicy::Always => ("Restart", "Restart"),
+            RestartPolicy::OnSuccess => ("Exit", "Restart"),
+            RestartPolicy::OnFailure => ("Restart", "Exit"),
+        };
+        set("AppExit", &format!("Default {}", default));
+        set("AppExit", &format!("0 {}", on_zero));
+        for code in service.success_exit_status.iter().flatten() {
+            set("AppExit", &format!("{} {}", code, on_zero));
+        }
+    }
+    for code in service.restart_prevent_exit_status.iter().flatten() {
+        set("AppExit", &format!("{} Exit", code));
+    }
+    if let Some(restart_sec) = service.restart_sec {
+        set("AppRestartDelay", &(restart_sec * 1000).to_string());
+    }
+    ret
+}
+
+/// Writes `%` as `%%`, as systemd expands `%` specifiers such as `%h` in
+/// most settings.
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+fn quote_systemd(word: &str) -> String {
+    let word = escape_specifiers(word);
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        return word;
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quotes a word for a batch file, where `%` expands variables even inside
+/// quotes and `& | < > ^ ( )` are only literal inside quotes.
+fn quote_cmd(word: &str) -> String {
+    let word = word.replace('%', "%%");
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || "\"&|<>^()".contains(c)) {
+        return word;
+    }
+    format!("\"{}\"", word.replace('"', "\"\""))
+}
+
+/// Joins lines ending with a backslash with the following line.
+fn logical_lines(content: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in content.lines() {
+        let line = if current.is_empty() { line } else { line.trim_start() };
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                current.push_str(line.trim_end());
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                lines.push(std::mem::take(&mut current));
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Splits a command line into words, honouring single and double quotes and
+/// the backslash escapes of systemd (`\\`, `\"`, `\'`, `\n`, `\t` and
+/// `\xNN`). Any other backslash is kept, so `C:\apps\demo.exe` stays a path.
+fn split_words(value: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                let escape = match chars.peek() {
+                    Some(&c @ ('\\' | '"' | '\'')) => Some((c, 1)),
+                    Some('n') => Some(('\n', 1)),
+                    Some('t') => Some(('\t', 1)),
+                    Some('x') => {
+                        let digits: String = chars.clone().skip(1).take(2).collect();
+                        match u8::from_str_radix(&digits, 16) {
+                            Ok(byte) if digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                                Some((char::from(byte), 3))
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                match escape {
+                    Some((c, len)) => {
+                        word.push(c);
+                        chars.nth(len - 1);
+                    }
+                    None => word.push('\\'),
+                }
+                in_word = true;
+            }
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (c, _) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(Error::Parse(format!("unterminated quote in `{}`", value)));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(value: &str) -> Vec<String> {
+        split_words(value).unwrap()
+    }
+
+    #[test]
+    fn split_words_keeps_windows_paths() {
+        assert_eq!(words(r"C:\apps\demo.exe --port 80"), [r"C:\apps\demo.exe", "--port", "80"]);
+        // `\\`, `\n`, `\t` and `\x` are escapes, as in systemd.
+        assert_eq!(words(r"\\\\server\share\run.cmd C:\\new"), [r"\\server\share\run.cmd", r"C:\new"]);
+        assert_eq!(words(r"D:\logs\"), [r"D:\logs\"]);
+    }
+
+    #[test]
+    fn split_words_handles_quotes_and_escapes() {
+        assert_eq!(words(r#""C:\Program Files\app.exe" 'a b' c\ d"#), [r"C:\Program Files\app.exe", "a b", "c\\", "d"]);
+        assert_eq!(words(r#"say "a \"quoted\" word" it\'s"#), ["say", r#"a "quoted" word"#, "it's"]);
+        assert_eq!(words(r"a\\b \x41\tz \xZZ"), [r"a\b", "A\tz", r"\xZZ"]);
+        assert!(split_words(r#"run "unterminated"#).is_err());
+    }
+
+    #[test]
+    fn parse_exec_start_and_environment() {
+        let content = "[Unit]\n\
+            Description=Demo\n\
+            [Service]\n\
+            ExecStart=C:\\apps\\demo.exe \\\n\
+            \x20   --port 80 \\\n\
+            \x20   --data \"D:\\my data\"\n\
+            Environment=HOME=C:\\Users\\demo \"GREETING=hello world\"\n";
+        let (config, untranslated) = parse(content, "demo").unwrap();
+        assert_eq!(config.service.path, r"C:\apps\demo.exe");
+        assert_eq!(config.service.args, Some(vec!["--port".to_string(), "80".to_string(), "--data".to_string(), r"D:\my data".to_string()]));
+        let env = config.service.env.unwrap();
+        assert_eq!(env["HOME"], r"C:\Users\demo");
+        assert_eq!(env["GREETING"], "hello world");
+        assert!(untranslated.is_empty(), "{:?}", untranslated);
+    }
+
+    #[test]
+    fn parse_exec_start_reset() {
+        let content = "[Service]\nExecStart=C:\\old.exe\nExecStart=\nExecStart=C:\\bin\\demo.exe -v\n";
+        let (config, untranslated) = parse(content, "demo").unwrap();
+        assert_eq!(config.service.path, r"C:\bin\demo.exe");
+        assert_eq!(config.service.args, Some(vec!["-v".to_string()]));
+        assert!(untranslated.is_empty(), "{:?}", untranslated);
+
+        assert!(parse("[Service]\nExecStart=C:\\old.exe\nExecStart=\n", "demo").is_err());
+    }
+
+    fn config(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn render_nssm_quotes_metacharacters() {
+        let config = config(r#"
+            [unit]
+            name = "demo"
+            description = "50% (beta)"
+            [service]
+            type = "Simple"
+            path = 'C:\apps\demo.exe'
+            args = ["--url=http://h/?a=1&b=2", "%PATH%", "plain"]
+            env = { GREETING = "a|b" }
+        "#);
+        let rendered = render_nssm(&config);
+        assert!(rendered.contains(r#"nssm install demo C:\apps\demo.exe "--url=http://h/?a=1&b=2" %%PATH%% plain"#), "{}", rendered);
+        assert!(rendered.contains(r#"nssm set demo Description "50%% (beta)""#), "{}", rendered);
+        assert!(rendered.contains(r#"nssm set demo AppEnvironmentExtra "GREETING=a|b""#), "{}", rendered);
+    }
+
+    #[test]
+    fn render_systemd_escapes_specifiers() {
+        let config = config(r#"
+            [unit]
+            name = "demo"
+            description = "100% sure"
+            [service]
+            type = "Simple"
+            path = 'C:\apps\demo.exe'
+            args = ["--rate=5%"]
+            env = { FORMAT = "%Y-%m" }
+        "#);
+        let rendered = render_systemd(&config);
+        assert!(rendered.contains("Description=100%% sure\n"), "{}", rendered);
+        assert!(rendered.contains(r#"ExecStart="C:\\apps\\demo.exe" --rate=5%%"#), "{}", rendered);
+        assert!(rendered.contains("Environment=FORMAT=%%Y-%%m\n"), "{}", rendered);
+    }
+}
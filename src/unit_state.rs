@@ -0,0 +1,120 @@
+use once_cell::sync::Lazy;
+use core::fmt;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use chrono::{DateTime, Local};
+use crate::journal;
+
+static UNIT_STATES: Lazy<Mutex<HashMap<String, UnitState>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// High level state of a unit, following systemd's `ActiveState`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ActiveState {
+    Inactive,
+    Activating,
+    Active,
+    Deactivating,
+    Failed,
+    Reloading,
+}
+
+/// Finer grained state refining `ActiveState`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SubState {
+    /// Not running.
+    Dead,
+    /// Checking conditions and spawning the process.
+    Start,
+    Running,
+    /// Exited and about to be started again by the restart policy.
+    AutoRestart,
+    /// Asked to exit, waiting for the stop timeout.
+    Stop,
+    /// Being restarted because its configuration changed.
+    Reload,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnitState {
+    pub active: ActiveState,
+    pub sub: SubState,
+    pub since: DateTime<Local>,
+    /// Why the unit entered its current state, e.g. an exit code or error.
+    pub result: Option<String>,
+}
+
+impl Default for UnitState {
+    fn default() -> Self {
+        UnitState {
+            active: ActiveState::Inactive,
+            sub: SubState::Dead,
+            since: Local::now(),
+            result: None,
+        }
+    }
+}
+
+impl fmt::Display for ActiveState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ActiveState::Inactive => "inactive",
+            ActiveState::Activating => "activating",
+            ActiveState::Active => "active",
+            ActiveState::Deactivating => "deactivating",
+            ActiveState::Failed => "failed",
+            ActiveState::Reloading => "reloading",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for SubState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SubState::Dead => "dead",
+            SubState::Start => "start",
+            SubState::Running => "running",
+            SubState::AutoRestart => "auto-restart",
+            SubState::Stop => "stop",
+            SubState::Reload => "reload",
+            SubState::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for UnitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) since {}", self.active, self.sub, self.since.format("%Y-%m-%d %H:%M:%S"))?;
+        if let Some(result) = &self.result {
+            write!(f, "; {}", result)?;
+        }
+        Ok(())
+    }
+}
+
+/// Moves `name` to a new state, logging the transition and recording it in
+/// the journal.
+pub fn set(name: &str, active: ActiveState, sub: SubState, result: Option<String>) {
+    let mut states = UNIT_STATES.lock().unwrap();
+    let state = states.entry(name.to_string()).or_default();
+    if state.active != active || state.sub != sub {
+        log::info!(unit = name, event = "state_changed"; "Service {} changed state {} ({}) -> {} ({}).", name, state.active, state.sub, active, sub);
+        let mut message = format!("{} ({}) -> {} ({})", state.active, state.sub, active, sub);
+        if let Some(result) = &result {
+            message.push_str(&format!("; {}", result));
+        }
+        journal::write(name, journal::MANAGER, None, &message);
+    }
+    *state = UnitState { active, sub, since: Local::now(), result };
+}
+
+pub fn fail(name: &str, result: String) {
+    set(name, ActiveState::Failed, SubState::Failed, Some(result));
+}
+
+/// Returns the state of `name`, `inactive (dead)` if it has never run.
+pub fn get(name: &str) -> UnitState {
+    UNIT_STATES.lock().unwrap().get(name).cloned().unwrap_or_default()
+}